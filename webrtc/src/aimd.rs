@@ -0,0 +1,206 @@
+//! Additive-increase/multiplicative-decrease control of the ping rate for
+//! --rtt-target.
+//!
+//! Once per [`PERIOD`] the controller compares the mean RTT of the echoes
+//! that arrived during that period with the target:
+//!
+//! - at or below the target, the rate grows by [`INCREASE`] pings/s;
+//! - above it, or when no echo arrived at all, the rate is multiplied by
+//!   [`DECREASE`].
+//!
+//! The rate stays within [`MIN_RATE`]..=[`MAX_RATE`]: never slower than the
+//! default of one ping a second, and never faster than tokio's millisecond
+//! timer can pace. Under load it saw-tooths just below the rate at which
+//! queueing pushes the RTT past the target. The reported steady-state rate
+//! is the mean over the last [`STEADY_PERIODS`] periods.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+/// Time between rate adjustments.
+pub const PERIOD: Duration = Duration::from_secs(1);
+
+/// Pings/s added after a period that met the target.
+pub const INCREASE: f64 = 10.0;
+
+/// Factor applied to the rate after a period that missed the target. Gentler
+/// than TCP's halving, so the sawtooth, and the error in the steady-state
+/// rate, stay small.
+pub const DECREASE: f64 = 0.8;
+
+/// Slowest rate, in pings/s.
+pub const MIN_RATE: f64 = 1.0;
+
+/// Fastest rate, in pings/s.
+pub const MAX_RATE: f64 = 1000.0;
+
+/// Periods averaged for the steady-state rate.
+pub const STEADY_PERIODS: usize = 10;
+
+/// Ping rate controller holding the RTT near a target.
+#[derive(Debug)]
+pub struct Aimd {
+    target: Duration,
+    rate: f64,
+    period_start: Duration,
+    /// RTTs of echoes received in the current period
+    total: Duration,
+    count: u32,
+    periods: u64,
+    /// Rates chosen in the last STEADY_PERIODS periods
+    recent: VecDeque<f64>,
+}
+
+impl Aimd {
+    pub fn new(target: Duration, now: Duration) -> Self {
+        Aimd {
+            target,
+            rate: MIN_RATE,
+            period_start: now,
+            total: Duration::ZERO,
+            count: 0,
+            periods: 0,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn on_rtt(&mut self, rtt: Duration) {
+        self.total += rtt;
+        self.count += 1;
+    }
+
+    /// Adjust the rate if a period has ended.
+    pub fn tick(&mut self, now: Duration) {
+        if now.saturating_sub(self.period_start) < PERIOD {
+            return;
+        }
+        let met = self.count > 0 && self.total / self.count <= self.target;
+        self.rate = if met {
+            self.rate + INCREASE
+        } else {
+            self.rate * DECREASE
+        }
+        .clamp(MIN_RATE, MAX_RATE);
+        self.periods += 1;
+        if self.recent.len() == STEADY_PERIODS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(self.rate);
+        self.restart(now);
+    }
+
+    /// Start a fresh period, discarding echoes seen so far (e.g. on resume,
+    /// so a pause does not read as a period without echoes).
+    pub fn restart(&mut self, now: Duration) {
+        self.period_start = now;
+        self.total = Duration::ZERO;
+        self.count = 0;
+    }
+
+    /// Time to wait between pings at the current rate.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate)
+    }
+
+    pub fn report(&self, payload: usize) -> RateReport {
+        let steady = if self.recent.is_empty() {
+            self.rate
+        } else {
+            self.recent.iter().sum::<f64>() / self.recent.len() as f64
+        };
+        RateReport {
+            target_ms: self.target.as_secs_f64() * 1000.0,
+            steady_rate_pps: steady,
+            steady_kbps: steady * payload as f64 * 8.0 / 1000.0,
+            periods: self.periods,
+        }
+    }
+}
+
+/// Where the controller settled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateReport {
+    pub target_ms: f64,
+    /// Mean ping rate over the last STEADY_PERIODS periods
+    pub steady_rate_pps: f64,
+    /// Payload throughput at that rate
+    pub steady_kbps: f64,
+    /// Rate adjustments made
+    pub periods: u64,
+}
+
+impl fmt::Display for RateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rtt target {:.2} ms: steady-state {:.1} pings/s ({:.1} kbps) after {} adjustments",
+            self.target_ms, self.steady_rate_pps, self.steady_kbps, self.periods
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path that queues above `capacity` pings/s: the RTT is 10 ms below
+    /// it and grows 1 ms per ping/s beyond it.
+    fn rtt_at(rate: f64, capacity: f64) -> Duration {
+        Duration::from_secs_f64((10.0 + (rate - capacity).max(0.0)) / 1000.0)
+    }
+
+    /// Run the controller against the model for `periods` periods. Returns
+    /// the report and the highest rate seen in the second half.
+    fn converge(target_ms: u64, capacity: f64, periods: u32) -> (RateReport, f64) {
+        let mut aimd = Aimd::new(Duration::from_millis(target_ms), Duration::ZERO);
+        let mut highest = 0.0_f64;
+        for period in 1..=periods {
+            let rate = 1.0 / aimd.interval().as_secs_f64();
+            if period > periods / 2 {
+                highest = highest.max(rate);
+            }
+            for _ in 0..rate.round() as u32 {
+                aimd.on_rtt(rtt_at(rate, capacity));
+            }
+            aimd.tick(PERIOD * period);
+        }
+        (aimd.report(100), highest)
+    }
+
+    #[test]
+    fn rate_settles_where_the_rtt_meets_the_target() {
+        for (target_ms, capacity) in [(30, 200.0), (60, 400.0)] {
+            // The RTT reaches the target 20 or 50 pings/s past capacity
+            let knee = capacity + target_ms as f64 - 10.0;
+            let (report, highest) = converge(target_ms, capacity, 200);
+            assert_eq!(report.periods, 200);
+            let steady = report.steady_rate_pps;
+            assert!(
+                steady > knee * DECREASE && steady <= knee + INCREASE,
+                "target {} ms: steady {:.1} pings/s, knee {}",
+                target_ms,
+                steady,
+                knee
+            );
+            // One step over the knee at most before backing off
+            assert!(highest <= knee + INCREASE, "{}", highest);
+            assert!((report.steady_kbps - steady * 0.8).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn silence_backs_off_to_the_minimum() {
+        let mut aimd = Aimd::new(Duration::from_millis(30), Duration::ZERO);
+        for period in 1..=5 {
+            aimd.on_rtt(Duration::from_millis(10));
+            aimd.tick(PERIOD * period);
+        }
+        assert_eq!(aimd.interval(), Duration::from_secs_f64(1.0 / 51.0));
+        for period in 6..=40 {
+            aimd.tick(PERIOD * period);
+        }
+        assert_eq!(aimd.interval(), Duration::from_secs(1));
+    }
+}
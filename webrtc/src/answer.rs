@@ -1,87 +1,532 @@
 use anyhow::Result;
 use bytes::Bytes;
-use std::io::{self, Write};
+use clap::Parser;
+use serde::Serialize;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
 use tokio::time::sleep;
-use webrtc::api::media_engine::MediaEngine;
-use webrtc::api::APIBuilder;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use webrtc::api::API;
 use webrtc::data_channel::data_channel_message::DataChannelMessage;
 use webrtc::data_channel::RTCDataChannel;
-use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc_latency::banner::{Banner, Timeline};
+use webrtc_latency::cli::{self, CommonArgs};
+use webrtc_latency::failure::{self, Failure, FailureKind};
+use webrtc_latency::keepalive::Keepalive;
+use webrtc_latency::loss::{self, SeededDrop};
+use webrtc_latency::munge::Rules;
+use webrtc_latency::wire::{self, FormatDescriptor, Frame};
+use webrtc_latency::{nat, path, peer, sdp, shutdown, signal, srflx, trickle};
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
+/// Answer an offer and echo latency pings back to it.
+#[derive(Parser, Serialize)]
+#[command(name = "answer")]
+struct Args {
+    #[command(flatten)]
+    common: CommonArgs,
 
-    // Build WebRTC API
-    let mut m = MediaEngine::default();
-    let api = APIBuilder::new().with_media_engine(m).build();
-
-    let config = RTCConfiguration {
-        ice_servers: vec![RTCIceServer {
-            urls: vec!["stun:stun.l.google.com:19302".to_string()],
-            ..Default::default()
-        }],
-        ..Default::default()
-    };
+    /// Subprotocol expected on incoming data channels
+    #[arg(long)]
+    protocol: Option<String>,
+
+    /// Close incoming channels whose subprotocol differs from --protocol
+    #[arg(long, requires = "protocol")]
+    reject_protocol_mismatch: bool,
+
+    /// Refuse to echo for a peer whose wire format is incompatible
+    #[arg(long)]
+    strict_seq: bool,
+
+    /// Drop this fraction of received pings instead of echoing them, chosen
+    /// by sequence number from --seed so the dropped set is reproducible
+    #[arg(long, value_name = "FRACTION", value_parser = loss::parse_fraction)]
+    recv_drop: Option<f64>,
+
+    /// Seed for --recv-drop
+    #[arg(long, default_value_t = 0, requires = "recv_drop")]
+    seed: u64,
+
+    /// Keep answering offers, one per stdin line, until interrupted
+    #[arg(long)]
+    listen: bool,
+
+    /// Connections served at once with --listen; later offers wait their turn
+    #[arg(
+        long,
+        default_value_t = 1,
+        requires = "listen",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    max_connections: u32,
+}
+
+/// One answered offer, alive until the peer goes away.
+struct Session {
+    pc: Arc<RTCPeerConnection>,
+    /// Errors that should end the connection, such as a refused wire format
+    fail_rx: mpsc::Receiver<anyhow::Error>,
+    /// Signalled once the data channel or the connection has closed
+    ended_rx: mpsc::Receiver<()>,
+    /// The offer carried no candidates, so they must be pasted separately
+    remote_trickles: bool,
+    /// Stops the tasks serving this connection
+    cancel: CancellationToken,
+}
+
+/// Answer one pasted offer: set up the echo responder, print the answer
+/// blob and hand back the live connection.
+async fn answer(
+    api: &API,
+    config: RTCConfiguration,
+    munge: Option<&Rules>,
+    args: &Args,
+    offer_line: &str,
+    shutdown: &CancellationToken,
+    tasks: &TaskTracker,
+) -> Result<Session> {
+    let cancel = shutdown.child_token();
+    let expected_protocol = args.protocol.clone();
+    let reject_mismatch = args.reject_protocol_mismatch;
+    let strict = args.strict_seq;
+    let json = args.common.json;
+    let quiet = args.common.quiet;
+    let recv_drop = args.recv_drop.map(|f| SeededDrop::new(f, args.seed));
+    let banner = args.common.show_banner();
+    let clock = Instant::now();
+    let timeline = Arc::new(Timeline::default());
+    let (fail_tx, fail_rx) = mpsc::channel(1);
+    let deadline_tx = fail_tx.clone();
+    let (ended_tx, ended_rx) = mpsc::channel(1);
 
     let pc = Arc::new(api.new_peer_connection(config).await?);
 
+    let ended = ended_tx.clone();
+    let timeline2 = Arc::clone(&timeline);
+    pc.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+        if state == RTCPeerConnectionState::Connected {
+            timeline2.mark("connected", clock.elapsed());
+        }
+        if matches!(
+            state,
+            RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed
+        ) {
+            let _ = ended.try_send(());
+        }
+        Box::pin(async {})
+    }));
+
     // When remote creates a DataChannel
+    let pc_weak = Arc::downgrade(&pc);
+    let timeline2 = Arc::clone(&timeline);
+    let cancel2 = cancel.clone();
+    let tasks2 = tasks.clone();
+    let keepalive = Keepalive::from_args(&args.common);
+    // Keepalives run on the first channel whose peer answers them
+    let keepalive_started = Arc::new(AtomicBool::new(false));
     pc.on_data_channel(Box::new(move |dc: Arc<RTCDataChannel>| {
+        let keepalive = keepalive.clone();
+        let keepalive_started = Arc::clone(&keepalive_started);
+        let cancel = cancel2.clone();
+        let tasks = tasks2.clone();
+        let expected_protocol = expected_protocol.clone();
+        let pc_weak = pc_weak.clone();
+        let fail_tx = fail_tx.clone();
+        let ended_tx = ended_tx.clone();
+        let timeline = Arc::clone(&timeline2);
         Box::pin(async move {
-            println!("DataChannel received: {}", dc.label());
+            println!(
+                "DataChannel received: {} (protocol: {:?})",
+                dc.label(),
+                dc.protocol()
+            );
+
+            // Check the subprotocol the offer announced
+            if let Some(expected) = expected_protocol {
+                if dc.protocol() != expected {
+                    eprintln!(
+                        "unexpected protocol {:?} on {}, expected {:?}",
+                        dc.protocol(),
+                        dc.label(),
+                        expected
+                    );
+                    if reject_mismatch {
+                        if let Err(e) = dc.close().await {
+                            eprintln!("close error: {:?}", e);
+                        }
+                        return;
+                    }
+                }
+            }
+
+            dc.on_error(peer::on_channel_error(fail_tx.clone()));
+
+            // The offer closing its connection shows up here first
+            dc.on_close(Box::new(move || {
+                let _ = ended_tx.try_send(());
+                Box::pin(async {})
+            }));
+
+            // Announce our wire format before anything else
+            let dc_hello = Arc::clone(&dc);
+            dc.on_open(Box::new(move || {
+                Box::pin(async move {
+                    timeline.mark("channel open", clock.elapsed());
+                    if let Some(pc) = pc_weak.upgrade() {
+                        path::print_selected_pair(&pc, json).await;
+                        if banner {
+                            Banner::collect("answer", &pc, &dc_hello, &timeline)
+                                .await
+                                .print(json);
+                        }
+                    }
+                    let hello = Frame::Hello(FormatDescriptor::current()).encode();
+                    if let Err(e) = dc_hello.send(&hello).await {
+                        eprintln!("send error: {:?}", e);
+                    }
+                })
+            }));
 
             // Send periodic messages to test latency other way
             let dc_sender = Arc::clone(&dc);
-            tokio::spawn(async move {
+            let stop = cancel.clone();
+            tasks.spawn(async move {
                 loop {
                     let msg = format!("Hello at {:?}", Instant::now());
                     if let Err(e) = dc_sender.send(&Bytes::from(msg.clone())).await {
                         eprintln!("send error: {:?}", e);
                         break;
                     }
-                    sleep(Duration::from_secs(2)).await;
+                    tokio::select! {
+                        _ = sleep(Duration::from_secs(2)) => {}
+                        _ = stop.cancelled() => break,
+                    }
                 }
             });
 
-            // Measure RTT when receiving timestamps
+            // Echo pings back so the offer can compute RTT
             let dc_reply = Arc::clone(&dc);
+            let mut peer_format = None;
+            let mut format_checked = false;
             dc.on_message(Box::new(move |msg: DataChannelMessage| {
                 let val = dc_reply.clone();
+                let fail_tx = fail_tx.clone();
+                let recv_ns = wire::wall_ns();
+                let frame = Frame::decode(&msg.data);
+                if let Some(Frame::Hello(peer)) = frame {
+                    peer_format = Some(peer);
+                    if let Some(keepalive) = &keepalive {
+                        if !peer.has(wire::FEATURE_KEEPALIVE) {
+                            eprintln!("warning: the offer does not answer keepalives");
+                        } else if !keepalive_started.swap(true, Ordering::SeqCst) {
+                            tasks.spawn(Arc::clone(keepalive).run(
+                                Arc::clone(&dc_reply),
+                                fail_tx.clone(),
+                                cancel.clone(),
+                            ));
+                        }
+                    }
+                }
+
+                // Check the peer's format once, on its first frame
+                let mut refused = None;
+                if frame.is_some() && !format_checked {
+                    format_checked = true;
+                    if let Err(why) = wire::check_peer_format(peer_format.as_ref()) {
+                        if strict {
+                            let message = format!("{}; refusing to echo (--strict-seq)", why);
+                            refused = Some(anyhow::Error::from(Failure::new(
+                                FailureKind::FormatMismatch,
+                                message,
+                            )));
+                        } else {
+                            eprintln!("warning: {}; echoing best-effort", why);
+                        }
+                    }
+                }
+
+                let keepalive = keepalive.clone();
                 Box::pin(async move {
-                    if msg.data.len() == 16 {
-                        // Echo back to compute RTT on offer side
-                        if let Err(e) = val.send(&Bytes::from(msg.data.clone())).await {
-                            eprintln!("reply send error: {:?}", e);
+                    if let Some(e) = refused {
+                        let _ = fail_tx.send(e).await;
+                        return;
+                    }
+                    match frame {
+                        Some(Frame::Hello(_)) => {}
+                        Some(Frame::Ping { seq, .. } | Frame::Stamped { seq, .. })
+                            if recv_drop.is_some_and(|d| d.drops(seq)) =>
+                        {
+                            if json {
+                                println!(
+                                    "{}",
+                                    serde_json::json!({ "event": "recv_drop", "seq": seq })
+                                );
+                            } else if !quiet {
+                                println!("dropped seq {} (--recv-drop)", seq);
+                            }
+                        }
+                        Some(Frame::Ping { .. }) | Some(Frame::Legacy { .. }) => {
+                            if let Err(e) = val.send(&msg.data).await {
+                                eprintln!("reply send error: {:?}", e);
+                            }
+                        }
+                        Some(Frame::Stamped {
+                            seq,
+                            sent_ns,
+                            padding,
+                            ..
+                        }) => {
+                            let echo = Frame::Stamped {
+                                seq,
+                                sent_ns,
+                                recv_ns,
+                                reply_ns: wire::wall_ns(),
+                                padding,
+                            };
+                            if let Err(e) = val.send(&echo.encode()).await {
+                                eprintln!("reply send error: {:?}", e);
+                            }
                         }
-                    } else {
-                        println!("Received: {}", String::from_utf8_lossy(&msg.data));
+                        Some(Frame::KeepalivePing { nonce }) => {
+                            let pong = Frame::KeepalivePong { nonce }.encode();
+                            if let Err(e) = val.send(&pong).await {
+                                eprintln!("keepalive send error: {:?}", e);
+                            }
+                        }
+                        Some(Frame::KeepalivePong { .. }) => {
+                            if let Some(keepalive) = &keepalive {
+                                keepalive.on_pong();
+                            }
+                        }
+                        None if quiet => {}
+                        None => println!("Received: {}", String::from_utf8_lossy(&msg.data)),
                     }
                 })
             }));
         })
     }));
 
-    // === Read offer SDP from stdin ===
-    println!("\n=== Paste OFFER from other peer and press Enter ===");
-    let mut line = String::new();
-    io::stdin().read_line(&mut line)?;
-    let offer_json = String::from_utf8(base64::decode(line.trim())?)?;
-    let offer = serde_json::from_str(&offer_json)?;
+    let mut offer = signal::decode_sdp(offer_line)?;
+    if offer.sdp_type == RTCSdpType::Answer {
+        return Err(Failure::new(
+            FailureKind::BothAnswerers,
+            "the pasted blob is an answer: both peers ran `answer`; run `offer` on one side",
+        )
+        .into());
+    }
+    let remote_trickles = sdp::candidates(&offer.sdp).is_empty();
+    peer::filter_remote_candidates(&mut offer, &args.common.address_filter());
+    let offer_sdp = offer.sdp.clone();
     pc.set_remote_description(offer).await?;
+    timeline.mark("offer applied", clock.elapsed());
 
     // === Create and show answer SDP ===
-    let answer = pc.create_answer(None).await?;
-    pc.set_local_description(answer.clone()).await?;
-    let sdp = serde_json::to_string(&answer)?;
+    let trickle_only = args.common.no_trickle_in_sdp;
+    let candidates = (trickle_only || args.common.print_candidates).then(|| trickle::collect(&pc));
+    let answer = peer::create_answer(&pc).await?;
+    let mut gather_complete = pc.gathering_complete_promise().await;
+    pc.set_local_description(answer).await?;
+
+    // Wait for ICE gathering so the answer carries our candidates
+    if !trickle_only {
+        let _ = gather_complete.recv().await;
+    }
+    let mut answer = pc
+        .local_description()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("missing local description"))?;
+    if trickle_only {
+        trickle::strip_candidates(&mut answer)?;
+    }
+    if let Some(rules) = munge {
+        rules.apply(&mut answer)?;
+    }
+    peer::check_gathered(&answer, &args.common)?;
+    if args.common.canonicalize_sdp {
+        signal::canonicalize(&mut answer)?;
+    }
+    timeline.mark("answer ready", clock.elapsed());
+    if args.common.warn_on_srflx_change {
+        tasks.spawn(srflx::watch(
+            Arc::downgrade(&pc),
+            peer::stun_urls(&args.common),
+            json,
+            cancel.clone(),
+        ));
+    }
     println!("\n=== Copy this ANSWER and send to the offer peer ===\n");
-    println!("{}", base64::encode(sdp));
+    println!("{}", signal::encode_sdp(&answer)?);
+    // Checked after printing the answer so the offer can report it too
+    if let Err(e) = peer::check_ip_versions(&answer.sdp, &offer_sdp) {
+        // With --listen the process goes on, so leave nothing running
+        cancel.cancel();
+        peer::close_quietly(pc).await;
+        return Err(e);
+    }
+    if let Some(secs) = args.common.connect_timeout {
+        tasks.spawn(peer::connect_deadline(
+            Arc::downgrade(&pc),
+            Duration::from_secs(secs),
+            deadline_tx,
+            cancel.clone(),
+        ));
+    }
+    if let Some(rx) = candidates {
+        let mid = sdp::first_mid(&answer.sdp);
+        tasks.spawn(trickle::print_local(rx, mid, trickle_only, cancel.clone()));
+    }
+
+    Ok(Session {
+        pc,
+        fail_rx,
+        ended_rx,
+        remote_trickles,
+        cancel,
+    })
+}
+
+/// Serve one --listen connection until the peer leaves, it is refused, or
+/// the listener stops. The permit is held for as long as the connection is.
+async fn serve(id: u64, mut session: Session, _permit: OwnedSemaphorePermit) {
+    // A channel error is followed by its close; report the error
+    let outcome = tokio::select! {
+        biased;
+        Some(e) = session.fail_rx.recv() => Err(e),
+        _ = session.ended_rx.recv() => Ok(()),
+        _ = session.cancel.cancelled() => Ok(()),
+    };
+    session.cancel.cancel();
+    peer::close_quietly(session.pc).await;
+    match outcome {
+        Ok(()) => println!("Connection {} closed", id),
+        Err(e) => eprintln!("connection {} ended: {:?}", id, e),
+    }
+}
+
+/// Answer offers read line by line from stdin, ready for the next one as
+/// soon as a connection slot is free.
+async fn listen(
+    api: API,
+    config: RTCConfiguration,
+    munge: Option<Rules>,
+    args: Args,
+) -> Result<()> {
+    let slots = Arc::new(Semaphore::new(args.max_connections as usize));
+    let shutdown = CancellationToken::new();
+    let tasks = TaskTracker::new();
+    let mut lines = signal::stdin_lines();
+    let mut next_id = 0;
+
+    let outcome = loop {
+        let permit = tokio::select! {
+            res = tokio::signal::ctrl_c() => break res.map_err(anyhow::Error::from),
+            permit = Arc::clone(&slots).acquire_owned() => permit?,
+        };
+        println!("\n=== Paste OFFER from the next peer and press Enter ===");
+        let line = tokio::select! {
+            res = tokio::signal::ctrl_c() => break res.map_err(anyhow::Error::from),
+            line = lines.recv() => match line {
+                Some(line) => line?,
+                None => break Ok(()),
+            },
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
 
-    // Keep alive
-    tokio::signal::ctrl_c().await?;
-    Ok(())
+        next_id += 1;
+        let answered = answer(
+            &api,
+            config.clone(),
+            munge.as_ref(),
+            &args,
+            &line,
+            &shutdown,
+            &tasks,
+        );
+        match answered.await {
+            Ok(session) => {
+                println!("Connection {} answered", next_id);
+                // stdin carries offers here, so there is nowhere to paste candidates
+                if session.remote_trickles {
+                    eprintln!(
+                        "warning: connection {} sent no candidates; trickled candidates \
+                         are not read with --listen",
+                        next_id
+                    );
+                }
+                tasks.spawn(serve(next_id, session, permit));
+            }
+            // A bad paste should not take the listener down
+            Err(e) => eprintln!("connection {} failed: {:?}", next_id, e),
+        }
+    };
+
+    shutdown::stop(&shutdown, &tasks).await;
+    outcome
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let args: Args = cli::parse();
+    let common = args.common.clone();
+    failure::finish(run(args).await, &common)
 }
 
+async fn run(args: Args) -> Result<()> {
+    if args.common.detect_nat_type {
+        return nat::run(&args.common.stun, args.common.json).await;
+    }
+
+    // Build WebRTC API
+    let api = peer::build_api(&args.common)?;
+    let config = peer::rtc_config(&args.common)?;
+    let munge = args.common.munge_rules()?;
+
+    if args.listen {
+        return listen(api, config, munge, args).await;
+    }
+
+    // === Read offer SDP from stdin ===
+    println!("\n=== Paste OFFER from other peer and press Enter ===");
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    let shutdown = CancellationToken::new();
+    let tasks = TaskTracker::new();
+    let answered = answer(
+        &api,
+        config,
+        munge.as_ref(),
+        &args,
+        &line,
+        &shutdown,
+        &tasks,
+    );
+    let mut session = answered.await?;
+    if session.remote_trickles {
+        println!("\n=== Paste the CANDIDATES from the other peer, one per line ===");
+        tasks.spawn(trickle::add_remote(
+            Arc::downgrade(&session.pc),
+            args.common.address_filter(),
+            signal::stdin_lines(),
+            shutdown.clone(),
+        ));
+    }
+
+    // Keep alive until interrupted or the format check fails
+    let outcome = tokio::select! {
+        res = tokio::signal::ctrl_c() => res.map_err(anyhow::Error::from),
+        Some(e) = session.fail_rx.recv() => Err(e),
+    };
+    shutdown::stop(&shutdown, &tasks).await;
+    peer::close_quietly(session.pc).await;
+    outcome
+}
@@ -0,0 +1,208 @@
+//! The --banner block: everything about an established session that a bug
+//! report needs, printed once when the data channel opens.
+
+use serde::Serialize;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::path::{self, SelectedPair};
+use crate::{peer, sdp};
+
+/// Setup milestones on the session clock, in the order they happened.
+#[derive(Debug, Default)]
+pub struct Timeline(Mutex<Vec<Milestone>>);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Milestone {
+    pub event: &'static str,
+    pub at_s: f64,
+}
+
+impl Timeline {
+    pub fn mark(&self, event: &'static str, at: Duration) {
+        self.0.lock().unwrap().push(Milestone {
+            event,
+            at_s: at.as_secs_f64(),
+        });
+    }
+
+    pub fn milestones(&self) -> Vec<Milestone> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Data channel parameters as negotiated.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelParams {
+    pub label: String,
+    pub protocol: String,
+    pub ordered: bool,
+    pub max_retransmits: u16,
+    pub max_packet_lifetime: u16,
+    pub max_message_size: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Banner {
+    pub role: &'static str,
+    pub pair: Option<SelectedPair>,
+    pub local_fingerprint: Option<String>,
+    pub remote_fingerprint: Option<String>,
+    pub channel: ChannelParams,
+    pub timings: Vec<Milestone>,
+}
+
+impl Banner {
+    pub async fn collect(
+        role: &'static str,
+        pc: &RTCPeerConnection,
+        dc: &RTCDataChannel,
+        timeline: &Timeline,
+    ) -> Self {
+        let local = pc.local_description().await.map(|d| d.sdp);
+        let remote = pc.remote_description().await.map(|d| d.sdp);
+        Banner {
+            role,
+            pair: path::selected_pair(pc).await,
+            local_fingerprint: local.as_deref().and_then(sdp::fingerprint),
+            remote_fingerprint: remote.as_deref().and_then(sdp::fingerprint),
+            channel: ChannelParams {
+                label: dc.label().to_string(),
+                protocol: dc.protocol().to_string(),
+                ordered: dc.ordered(),
+                max_retransmits: dc.max_retransmits(),
+                max_packet_lifetime: dc.max_packet_lifetime(),
+                max_message_size: peer::max_message_size(pc).await,
+            },
+            timings: timeline.milestones(),
+        }
+    }
+
+    /// Print the banner, as a JSON line when `json` is set.
+    pub fn print(&self, json: bool) {
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({ "event": "banner", "banner": self })
+            );
+        } else {
+            println!("{}", self);
+        }
+    }
+}
+
+impl fmt::Display for Banner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rule = "=".repeat(64);
+        let none = || "-".to_string();
+        writeln!(f, "{}", rule)?;
+        writeln!(f, "role:               {}", self.role)?;
+        match &self.pair {
+            Some(pair) => {
+                writeln!(f, "network:            {}", pair.local.protocol)?;
+                writeln!(f, "local candidate:    {}", pair.local)?;
+                writeln!(f, "remote candidate:   {}", pair.remote)?;
+            }
+            None => writeln!(f, "selected pair:      -")?,
+        }
+        writeln!(
+            f,
+            "local fingerprint:  {}",
+            self.local_fingerprint.clone().unwrap_or_else(none)
+        )?;
+        writeln!(
+            f,
+            "remote fingerprint: {}",
+            self.remote_fingerprint.clone().unwrap_or_else(none)
+        )?;
+        let c = &self.channel;
+        writeln!(
+            f,
+            "channel:            {:?} protocol {:?}, {}, max-retransmits {}, \
+             max-packet-lifetime {}, max-message-size {}",
+            c.label,
+            c.protocol,
+            if c.ordered { "ordered" } else { "unordered" },
+            c.max_retransmits,
+            c.max_packet_lifetime,
+            c.max_message_size
+        )?;
+        let timings: Vec<String> = self
+            .timings
+            .iter()
+            .map(|m| format!("{} {:.3}s", m.event, m.at_s))
+            .collect();
+        writeln!(f, "setup:              {}", timings.join(", "))?;
+        write!(f, "{}", rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::Endpoint;
+
+    fn endpoint(ip: &str, port: u16, candidate_type: &str) -> Endpoint {
+        Endpoint {
+            protocol: "udp4".to_string(),
+            ip: ip.to_string(),
+            port,
+            candidate_type: candidate_type.to_string(),
+        }
+    }
+
+    fn banner() -> Banner {
+        let timeline = Timeline::default();
+        timeline.mark("offer ready", Duration::from_millis(120));
+        timeline.mark("connected", Duration::from_millis(1480));
+        Banner {
+            role: "offer",
+            pair: Some(SelectedPair {
+                local: endpoint("192.0.2.2", 41000, "host"),
+                remote: endpoint("198.51.100.7", 3478, "srflx"),
+            }),
+            local_fingerprint: Some("sha-256 AA:BB".to_string()),
+            remote_fingerprint: None,
+            channel: ChannelParams {
+                label: "latency".to_string(),
+                protocol: "myproto".to_string(),
+                ordered: false,
+                max_retransmits: 2,
+                max_packet_lifetime: 0,
+                max_message_size: 65535,
+            },
+            timings: timeline.milestones(),
+        }
+    }
+
+    #[test]
+    fn banner_shows_the_session() {
+        let text = banner().to_string();
+        for line in [
+            "role:               offer",
+            "network:            udp4",
+            "local candidate:    192.0.2.2:41000 (host)",
+            "remote candidate:   198.51.100.7:3478 (srflx)",
+            "local fingerprint:  sha-256 AA:BB",
+            "remote fingerprint: -",
+            "channel:            \"latency\" protocol \"myproto\", unordered, max-retransmits 2, \
+             max-packet-lifetime 0, max-message-size 65535",
+            "setup:              offer ready 0.120s, connected 1.480s",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{:?} missing from\n{}",
+                line,
+                text
+            );
+        }
+
+        let json = serde_json::to_value(banner()).unwrap();
+        assert_eq!(json["pair"]["remote"]["candidate_type"], "srflx");
+        assert_eq!(json["channel"]["max_message_size"], 65535);
+        assert_eq!(json["timings"][1]["event"], "connected");
+    }
+}
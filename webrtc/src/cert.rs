@@ -0,0 +1,86 @@
+//! DTLS certificates derived from a passphrase.
+//!
+//! The passphrase *is* the private key: anyone who knows it can impersonate
+//! the peer, and a weak passphrase can be brute-forced offline from the
+//! fingerprint. Use it for reproducible fingerprints in test fleets, not as a
+//! secret you would not also put in a key file.
+
+use anyhow::{anyhow, Result};
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair, PKCS_ED25519};
+use ring::pbkdf2;
+use ring::signature::{Ed25519KeyPair, KeyPair as _};
+use std::num::NonZeroU32;
+use webrtc::peer_connection::certificate::RTCCertificate;
+
+const SALT: &[u8] = b"webrtc_latency/cert-passphrase/v1";
+const ITERATIONS: u32 = 100_000;
+
+// PKCS#8 v2 framing for an Ed25519 seed followed by its public key
+const PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x53, 0x02, 0x01, 0x01, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+const PKCS8_PUBLIC_KEY_TAG: [u8; 5] = [0xa1, 0x23, 0x03, 0x21, 0x00];
+
+/// Derive an Ed25519 certificate whose fingerprint depends only on `passphrase`.
+///
+/// Ed25519 signatures are deterministic and rcgen's default validity and
+/// serial number are fixed, so the whole certificate is reproducible.
+pub fn from_passphrase(passphrase: &str) -> Result<RTCCertificate> {
+    let mut seed = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(ITERATIONS).unwrap(),
+        SALT,
+        passphrase.as_bytes(),
+        &mut seed,
+    );
+    let public_key = Ed25519KeyPair::from_seed_unchecked(&seed)
+        .map_err(|e| anyhow!("cannot derive key from passphrase: {}", e))?
+        .public_key()
+        .as_ref()
+        .to_vec();
+
+    let mut pkcs8 = PKCS8_PREFIX.to_vec();
+    pkcs8.extend_from_slice(&seed);
+    pkcs8.extend_from_slice(&PKCS8_PUBLIC_KEY_TAG);
+    pkcs8.extend_from_slice(&public_key);
+
+    let mut params = CertificateParams::default();
+    params.alg = &PKCS_ED25519;
+    params.key_pair = Some(KeyPair::from_der(&pkcs8)?);
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "webrtc_latency");
+    Ok(RTCCertificate::from_params(params)?)
+}
+
+/// Format the certificate's fingerprints as they appear in SDP.
+pub fn fingerprint(cert: &RTCCertificate) -> String {
+    cert.get_fingerprints()
+        .iter()
+        .map(|f| format!("{} {}", f.algorithm, f.value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_passphrase_same_fingerprint() {
+        let a = fingerprint(&from_passphrase("fleet secret").unwrap());
+        let b = fingerprint(&from_passphrase("fleet secret").unwrap());
+        let other = fingerprint(&from_passphrase("fleet secret!").unwrap());
+        assert_eq!(a, b);
+        assert_ne!(a, other);
+        // Pinned, so a change to the derivation or to rcgen's defaults that
+        // would break fingerprints pinned by existing fleets fails here
+        assert_eq!(
+            a,
+            "sha-256 42:6a:7f:d8:28:56:0e:00:12:a8:8d:0d:34:c4:e7:2c:28:81:00:11:05:6f:46:e2:\
+             6e:3f:90:29:cd:58:ab:7f"
+        );
+    }
+}
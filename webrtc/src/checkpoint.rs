@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::stats::{Sample, SummaryReport};
+
+/// Partial results persisted periodically during long runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub summary: SummaryReport,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<Sample>,
+}
+
+impl Checkpoint {
+    /// Write the checkpoint next to `path` and rename it into place, so a
+    /// crash mid-write never leaves a truncated file behind.
+    pub fn write_atomic(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("writing {}", Path::new(&tmp).display()))?;
+        fs::rename(&tmp, path).with_context(|| format!("replacing {}", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::stats::Stats;
+
+    #[test]
+    fn written_checkpoint_reads_back() {
+        let mut stats = Stats::new();
+        for seq in 0..5 {
+            let sent = Duration::from_millis(seq * 1000);
+            stats.on_sent(seq, sent);
+            if seq != 2 {
+                stats.on_echo(seq, sent, sent + Duration::from_millis(10 + seq));
+            }
+        }
+        let checkpoint = Checkpoint {
+            summary: stats.summary(Duration::from_secs(5)),
+            samples: stats.samples().to_vec(),
+        };
+
+        let dir = std::env::temp_dir().join(format!("checkpoint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.json");
+        checkpoint.write_atomic(&path).unwrap();
+        // Overwriting replaces the file rather than failing on it
+        checkpoint.write_atomic(&path).unwrap();
+        let read: Checkpoint = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        let leftovers = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(leftovers, 1, "the .tmp file was left behind");
+        assert_eq!(read.summary.sent, 5);
+        assert_eq!(read.summary.received, 4);
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(&checkpoint).unwrap()
+        );
+        let seqs: Vec<u64> = read.samples.iter().map(|s| s.seq).collect();
+        assert_eq!(seqs, [0, 1, 3, 4]);
+    }
+}
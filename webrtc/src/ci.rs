@@ -0,0 +1,171 @@
+//! 95% confidence intervals for the summary.
+//!
+//! The interval on the mean comes from the standard error, using the
+//! normal approximation: mean ± 1.96 · s / √n. Percentiles have no such
+//! simple form, so --bootstrap estimates theirs by resampling the RTTs with
+//! replacement and taking the middle 95% of the resampled percentiles. The
+//! resampling is seeded, so the same samples always give the same bounds.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::loss::splitmix;
+use crate::stats::percentile;
+
+/// z-score bounding the middle 95% of a normal distribution.
+const Z_95: f64 = 1.96;
+
+/// A confidence interval, in ms.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2}-{:.2}", self.lo, self.hi)
+    }
+}
+
+/// Standard-error interval on the mean, or `None` with fewer than two
+/// values.
+pub fn mean_ci(values: &[f64]) -> Option<Interval> {
+    if values.len() < 2 {
+        return None;
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    let half = Z_95 * (variance / n).sqrt();
+    Some(Interval {
+        lo: mean - half,
+        hi: mean + half,
+    })
+}
+
+/// Bootstrap intervals on the reported percentiles.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootstrapReport {
+    pub resamples: u64,
+    pub p50_ms: Interval,
+    pub p95_ms: Interval,
+    pub p99_ms: Interval,
+}
+
+impl fmt::Display for BootstrapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bootstrap 95% CI p50/p95/p99 = {}/{}/{} ms ({} resamples)",
+            self.p50_ms, self.p95_ms, self.p99_ms, self.resamples
+        )
+    }
+}
+
+/// Percentile intervals from `resamples` resamples of `values`, or `None`
+/// with fewer than two values.
+pub fn bootstrap(values: &[f64], resamples: u64) -> Option<BootstrapReport> {
+    if values.len() < 2 {
+        return None;
+    }
+    let n = values.len();
+    let mut draws = 0u64;
+    let mut resample = vec![0.0; n];
+    let mut estimates = [(); 3].map(|_| Vec::with_capacity(resamples as usize));
+    for _ in 0..resamples {
+        for slot in resample.iter_mut() {
+            // Scale the draw onto 0..n without the bias of a modulo
+            let i = ((splitmix(0, draws) as u128 * n as u128) >> 64) as usize;
+            draws += 1;
+            *slot = values[i];
+        }
+        resample.sort_by(f64::total_cmp);
+        for (estimate, pct) in estimates.iter_mut().zip([50.0, 95.0, 99.0]) {
+            estimate.extend(percentile(&resample, pct));
+        }
+    }
+    let [p50, p95, p99] = estimates.map(|mut v| {
+        v.sort_by(f64::total_cmp);
+        Interval {
+            lo: percentile(&v, 2.5).unwrap_or(f64::NAN),
+            hi: percentile(&v, 97.5).unwrap_or(f64::NAN),
+        }
+    });
+    Some(BootstrapReport {
+        resamples,
+        p50_ms: p50,
+        p95_ms: p95,
+        p99_ms: p99,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn mean_interval_matches_the_standard_error() {
+        // Mean 14, sample variance 10, so the standard error is √2
+        let ci = mean_ci(&[16.0, 10.0, 14.0, 18.0, 12.0]).unwrap();
+        assert_near(ci.lo, 11.228141);
+        assert_near(ci.hi, 16.771859);
+        assert_eq!(ci.to_string(), "11.23-16.77");
+
+        // Repeating the values narrows it as n grows
+        let many: Vec<f64> = [10.0, 12.0, 14.0, 16.0, 18.0].repeat(4);
+        let narrow = mean_ci(&many).unwrap();
+        assert_near(narrow.hi - 14.0, Z_95 * (160.0f64 / 19.0 / 20.0).sqrt());
+
+        let flat = mean_ci(&[5.0, 5.0, 5.0]).unwrap();
+        assert_eq!((flat.lo, flat.hi), (5.0, 5.0));
+        assert!(mean_ci(&[5.0]).is_none());
+        assert!(mean_ci(&[]).is_none());
+    }
+
+    #[test]
+    fn bootstrap_bounds_straddle_the_percentiles() {
+        // 10.0 to 29.9 ms in steps of 0.1, shuffled
+        let values: Vec<f64> = (0..200)
+            .map(|i| 10.0 + (i * 37 % 200) as f64 / 10.0)
+            .collect();
+        let mut sorted = values.clone();
+        sorted.sort_by(f64::total_cmp);
+
+        let report = bootstrap(&values, 500).unwrap();
+        assert_eq!(report.resamples, 500);
+        for (ci, pct) in [
+            (report.p50_ms, 50.0),
+            (report.p95_ms, 95.0),
+            (report.p99_ms, 99.0),
+        ] {
+            let estimate = percentile(&sorted, pct).unwrap();
+            assert!(
+                ci.lo <= estimate && estimate <= ci.hi,
+                "p{}: {} outside {}",
+                pct,
+                estimate,
+                ci
+            );
+            assert!(ci.lo < ci.hi, "p{}: {}", pct, ci);
+        }
+
+        // Seeded, so the same values always give the same bounds
+        let again = bootstrap(&values, 500).unwrap();
+        assert_eq!(again.p50_ms, report.p50_ms);
+        assert_eq!(again.p95_ms, report.p95_ms);
+        assert_eq!(again.p99_ms, report.p99_ms);
+
+        assert!(bootstrap(&[12.0], 500).is_none());
+        assert!(bootstrap(&[], 500).is_none());
+    }
+}
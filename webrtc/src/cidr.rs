@@ -0,0 +1,148 @@
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network in `address/prefix` notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address in {:?}", s))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("invalid prefix length in {:?}", s))?,
+            None => max,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Which remote candidate addresses we are willing to pair with.
+#[derive(Debug, Clone, Default)]
+pub struct AddressFilter {
+    /// When non-empty, only addresses inside one of these are permitted
+    pub allow: Vec<Cidr>,
+    /// Addresses inside any of these are refused
+    pub deny: Vec<Cidr>,
+}
+
+impl AddressFilter {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Whether a candidate address is permitted. Hostnames (mDNS candidates)
+    /// cannot be checked and are only permitted without an allowlist.
+    pub fn permits(&self, address: &str) -> bool {
+        let Ok(ip) = address.parse::<IpAddr>() else {
+            return self.allow.is_empty();
+        };
+        if self.deny.iter().any(|c| c.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip))
+    }
+}
+
+impl Serialize for Cidr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn prefix_edges() {
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(cidr("192.0.2.7/32").contains(ip("192.0.2.7")));
+        assert!(!cidr("192.0.2.7/32").contains(ip("192.0.2.6")));
+        assert_eq!(cidr("192.0.2.7"), cidr("192.0.2.7/32"));
+        assert!(cidr("10.1.0.0/15").contains(ip("10.0.255.255")));
+        assert!(!cidr("10.1.0.0/15").contains(ip("10.2.0.0")));
+
+        assert!(cidr("::/0").contains(ip("fd00::2")));
+        assert!(!cidr("::/0").contains(ip("192.0.2.7")));
+        assert!(cidr("fd00::/8").contains(ip("fdff:1::1")));
+        assert!(!cidr("fd00::/8").contains(ip("fe80::1")));
+        assert!(cidr("2001:db8::/33").contains(ip("2001:db8:7fff::1")));
+        assert!(!cidr("2001:db8::/33").contains(ip("2001:db8:8000::1")));
+        assert!(cidr("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!cidr("2001:db8::1/128").contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn invalid_prefixes_are_refused() {
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        assert!("::/129".parse::<Cidr>().is_err());
+        assert!("192.0.2.0/".parse::<Cidr>().is_err());
+        assert!("host.local/24".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = AddressFilter {
+            allow: vec![cidr("192.0.2.0/24")],
+            deny: vec![cidr("192.0.2.128/25")],
+        };
+        assert!(filter.permits("192.0.2.1"));
+        assert!(!filter.permits("192.0.2.200"));
+        assert!(!filter.permits("198.51.100.1"));
+        assert!(!filter.permits("abcd.local"));
+        let deny_only = AddressFilter {
+            allow: Vec::new(),
+            deny: vec![cidr("192.0.2.128/25")],
+        };
+        assert!(deny_only.permits("abcd.local"));
+    }
+}
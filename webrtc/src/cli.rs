@@ -0,0 +1,305 @@
+use anyhow::{anyhow, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, Command, Parser};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::PathBuf;
+
+use crate::cidr::{AddressFilter, Cidr};
+use crate::munge::Rules;
+
+/// Options shared by the offer and answer binaries.
+///
+/// ICE servers come from the command line first, then from the
+/// RC_WEBRTC_* environment variables, and fall back to Google's public
+/// STUN server when neither names a STUN server.
+#[derive(Args, Debug, Clone, Serialize)]
+pub struct CommonArgs {
+    /// STUN server URL, repeatable or comma-separated
+    #[arg(long = "stun", env = "RC_WEBRTC_STUN", value_delimiter = ',')]
+    pub stun: Vec<String>,
+
+    /// TURN server URL, e.g. turn:turn.example.com:3478
+    #[arg(long, env = "RC_WEBRTC_TURN_URL")]
+    pub turn_url: Option<String>,
+
+    /// TURN username
+    #[arg(long, env = "RC_WEBRTC_TURN_USER")]
+    pub turn_user: Option<String>,
+
+    /// TURN password
+    #[arg(long, env = "RC_WEBRTC_TURN_PASS", hide_env_values = true)]
+    pub turn_pass: Option<String>,
+
+    /// Lowest local UDP port ICE may bind (use with --port-max)
+    #[arg(long, requires = "port_max")]
+    pub port_min: Option<u16>,
+
+    /// Highest local UDP port ICE may bind (use with --port-min)
+    #[arg(long, requires = "port_min")]
+    pub port_max: Option<u16>,
+
+    /// Only pair with remote candidates inside these networks
+    /// (repeatable or comma-separated, e.g. 192.168.0.0/16)
+    #[arg(long, value_delimiter = ',')]
+    pub allow_cidr: Vec<Cidr>,
+
+    /// Never pair with remote candidates inside these networks
+    #[arg(long, value_delimiter = ',')]
+    pub deny_cidr: Vec<Cidr>,
+
+    /// Derive the DTLS certificate from this passphrase for a stable
+    /// fingerprint (the passphrase is effectively the private key)
+    #[arg(long)]
+    pub cert_passphrase: Option<String>,
+
+    /// Rewrite lines of the local SDP before sending it, per the rules in
+    /// this JSON file
+    #[arg(long)]
+    pub sdp_munge: Option<PathBuf>,
+
+    /// Put the local SDP in canonical form before encoding it, so that
+    /// equivalent descriptions give identical blobs (see sdp::canonicalize)
+    #[arg(long)]
+    pub canonicalize_sdp: bool,
+
+    /// Send the SDP without candidates and print each candidate as its own
+    /// line as it is gathered, for strict trickle-only peers
+    #[arg(long)]
+    pub no_trickle_in_sdp: bool,
+
+    /// Refuse to send the SDP unless it carries at least this many usable
+    /// (non-loopback) candidates
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "no_trickle_in_sdp",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub min_candidates: Option<u64>,
+
+    /// Refuse to send the SDP unless it carries a server-reflexive candidate
+    #[arg(long, conflicts_with = "no_trickle_in_sdp")]
+    pub require_srflx: bool,
+
+    /// Print each gathered candidate as a JSON line ready to paste into a
+    /// trickle-only peer, even when the SDP carries them too
+    #[arg(long)]
+    pub print_candidates: bool,
+
+    /// Print a block summarizing the session once the data channel opens
+    #[arg(long)]
+    pub banner: bool,
+
+    /// Skip the banner and per-message output (overrides --banner)
+    #[arg(long)]
+    pub quiet: bool,
+
+    /// Warn when our server-reflexive mapping changes mid-session
+    #[arg(long)]
+    pub warn_on_srflx_change: bool,
+
+    /// Print connection details as JSON lines
+    #[arg(long)]
+    pub json: bool,
+
+    /// Classify the NAT in front of this host using the STUN servers, then
+    /// exit without connecting
+    #[arg(long)]
+    pub detect_nat_type: bool,
+
+    /// Give up unless the connection is up this many seconds after the
+    /// remote description is applied
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub connect_timeout: Option<u64>,
+
+    /// Send an application-level keepalive every this many seconds, apart
+    /// from the latency pings, and end the connection once the peer stops
+    /// answering them
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub keepalive: Option<u64>,
+
+    /// Seconds without a keepalive answer before the peer counts as gone
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 10,
+        requires = "keepalive",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub keepalive_timeout: u64,
+
+    /// On failure, print a JSON object with the error kind, message and
+    /// context to stderr instead of the usual text
+    #[arg(long)]
+    pub error_json: bool,
+
+    /// On failure, also write that JSON object to this file (with or
+    /// without --error-json)
+    #[arg(long)]
+    pub error_file: Option<PathBuf>,
+
+    /// Print a command line equivalent to the resolved options and exit
+    #[arg(long)]
+    pub print_effective_args: bool,
+}
+
+impl CommonArgs {
+    pub fn show_banner(&self) -> bool {
+        self.banner && !self.quiet
+    }
+
+    pub fn munge_rules(&self) -> Result<Option<Rules>> {
+        self.sdp_munge.as_deref().map(Rules::load).transpose()
+    }
+
+    pub fn address_filter(&self) -> AddressFilter {
+        AddressFilter {
+            allow: self.allow_cidr.clone(),
+            deny: self.deny_cidr.clone(),
+        }
+    }
+}
+
+/// Parse the command line, handling --print-effective-args.
+pub fn parse<T: Parser + Serialize>() -> T {
+    let matches = T::command().get_matches();
+    let args = T::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if matches.get_flag("print_effective_args") {
+        match effective_args(&T::command(), &args, &matches) {
+            Ok(line) => println!("{}", line),
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+    args
+}
+
+/// Render a canonical command line that parses back to `args`.
+///
+/// Options left at their defaults are omitted; values taken from the
+/// environment are written out as flags so the line does not depend on it.
+pub fn effective_args<T: Serialize>(
+    cmd: &Command,
+    args: &T,
+    matches: &ArgMatches,
+) -> Result<String> {
+    let mut fields = Vec::new();
+    flatten(serde_json::to_value(args)?, &mut fields);
+
+    let mut words = vec![cmd.get_name().to_string()];
+    for (id, value) in fields {
+        if id == "print_effective_args" {
+            continue;
+        }
+        match matches.value_source(&id) {
+            Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable) => {}
+            _ => continue,
+        }
+        let long = cmd
+            .get_arguments()
+            .find(|a| a.get_id() == id.as_str())
+            .and_then(|a| a.get_long())
+            .ok_or_else(|| anyhow!("no flag for option {}", id))?;
+        let flag = format!("--{}", long);
+        match value {
+            Value::Bool(true) => words.push(flag),
+            Value::Array(items) => {
+                for item in items {
+                    words.push(flag.clone());
+                    words.push(quote(&scalar(item)));
+                }
+            }
+            Value::Bool(false) | Value::Null => {}
+            other => {
+                words.push(flag);
+                words.push(quote(&scalar(other)));
+            }
+        }
+    }
+    Ok(words.join(" "))
+}
+
+/// Collect leaf fields, descending into flattened option groups.
+fn flatten(value: Value, out: &mut Vec<(String, Value)>) {
+    if let Value::Object(map) = value {
+        for (key, value) in map {
+            match value {
+                Value::Object(_) => flatten(value, out),
+                value => out.push((key, value)),
+            }
+        }
+    }
+}
+
+fn scalar(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+/// Quote a word for POSIX shells when it contains anything special.
+fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:,=@+%".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::peer;
+    use crate::testdata::{lock_env, parse_common_locked as parse};
+
+    const ENV_VARS: [&str; 4] = [
+        "RC_WEBRTC_STUN",
+        "RC_WEBRTC_TURN_URL",
+        "RC_WEBRTC_TURN_USER",
+        "RC_WEBRTC_TURN_PASS",
+    ];
+
+    #[test]
+    fn ice_servers_come_from_the_environment() {
+        let _env = lock_env();
+        let values = [
+            "stun:a.example:3478,stun:b.example:3478",
+            "turn:turn.example:3478",
+            "alice",
+            "s3cret",
+        ];
+        for (var, value) in ENV_VARS.iter().zip(values) {
+            std::env::set_var(var, value);
+        }
+        let from_env = peer::rtc_config(&parse(&[])).unwrap().ice_servers;
+        let from_cli = peer::rtc_config(&parse(&["--stun", "stun:cli.example:1"]))
+            .unwrap()
+            .ice_servers;
+        for var in ENV_VARS {
+            std::env::remove_var(var);
+        }
+        let defaults = peer::rtc_config(&parse(&[])).unwrap().ice_servers;
+
+        assert_eq!(from_env.len(), 2);
+        assert_eq!(
+            from_env[0].urls,
+            ["stun:a.example:3478", "stun:b.example:3478"]
+        );
+        assert_eq!(from_env[1].urls, ["turn:turn.example:3478"]);
+        assert_eq!(from_env[1].username, "alice");
+        assert_eq!(from_env[1].credential, "s3cret");
+        // A flag wins over its variable; the others still apply
+        assert_eq!(from_cli[0].urls, ["stun:cli.example:1"]);
+        assert_eq!(from_cli[1].username, "alice");
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(defaults[0].urls, ["stun:stun.l.google.com:19302"]);
+    }
+}
@@ -0,0 +1,138 @@
+//! A local Unix socket for steering a running measurement.
+//!
+//! Clients send one command per line and get one line back:
+//! `pause` stops the ping generator without closing the connection,
+//! `resume` starts it again, and `status` reports which state it is in.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// Bind the control socket, replacing a stale one left by an earlier run.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            let _ = fs::remove_file(path);
+        }
+    }
+    UnixListener::bind(path).with_context(|| format!("binding {}", path.display()))
+}
+
+/// Accept control clients until the listener fails or `cancel` fires, then
+/// wait for connected clients to be dropped. `paused` is shared with the
+/// ping loop.
+pub async fn serve(listener: UnixListener, paused: watch::Sender<bool>, cancel: CancellationToken) {
+    let clients = TaskTracker::new();
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = cancel.cancelled() => break,
+        };
+        match accepted {
+            Ok((stream, _)) => {
+                clients.spawn(handle(stream, paused.clone(), cancel.clone()));
+            }
+            Err(e) => {
+                eprintln!("control socket error: {}", e);
+                break;
+            }
+        }
+    }
+    clients.close();
+    clients.wait().await;
+}
+
+async fn handle(stream: UnixStream, paused: watch::Sender<bool>, cancel: CancellationToken) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line,
+            _ = cancel.cancelled() => return,
+        };
+        let Ok(Some(line)) = line else {
+            return;
+        };
+        let reply = match line.trim() {
+            "pause" => {
+                paused.send_replace(true);
+                "ok paused".to_string()
+            }
+            "resume" => {
+                paused.send_replace(false);
+                "ok running".to_string()
+            }
+            "status" if *paused.borrow() => "paused".to_string(),
+            "status" => "running".to_string(),
+            "" => continue,
+            other => format!(
+                "error: unknown command {:?} (expected pause, resume or status)",
+                other
+            ),
+        };
+        if write
+            .write_all(format!("{}\n", reply).as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::Lines;
+    use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+
+    struct Client {
+        write: OwnedWriteHalf,
+        replies: Lines<BufReader<OwnedReadHalf>>,
+    }
+
+    impl Client {
+        async fn send(&mut self, command: &str) -> String {
+            let line = format!("{}\n", command);
+            self.write.write_all(line.as_bytes()).await.unwrap();
+            self.replies.next_line().await.unwrap().unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume_over_the_socket() {
+        let path = std::env::temp_dir().join(format!("control-{}.sock", std::process::id()));
+        let listener = bind(&path).unwrap();
+        let (paused_tx, paused) = watch::channel(false);
+        let cancel = CancellationToken::new();
+        let server = tokio::spawn(serve(listener, paused_tx, cancel.clone()));
+
+        let (read, write) = UnixStream::connect(&path).await.unwrap().into_split();
+        let mut client = Client {
+            write,
+            replies: BufReader::new(read).lines(),
+        };
+        assert_eq!(client.send("status").await, "running");
+        assert_eq!(client.send("pause").await, "ok paused");
+        assert!(*paused.borrow());
+        assert_eq!(client.send("status").await, "paused");
+        assert_eq!(client.send("resume").await, "ok running");
+        assert!(!*paused.borrow());
+        let reply = client.send("stop").await;
+        assert!(
+            reply.starts_with("error: unknown command \"stop\""),
+            "{}",
+            reply
+        );
+
+        cancel.cancel();
+        server.await.unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+use crate::stats::{ms, SummaryReport};
+
+/// How often the --oneline status is redrawn.
+pub const ONELINE_REFRESH: Duration = Duration::from_millis(500);
+
+/// Whether stdout can take carriage-return redraws.
+pub fn stdout_is_tty() -> bool {
+    io::stdout().is_terminal()
+}
+
+/// Render the single status line shown by --oneline.
+pub fn format_oneline(report: &SummaryReport) -> String {
+    let up = report.uptime_s as u64;
+    format!(
+        "rtt {} ms | mean {} ms | p95 {} ms | loss {:.1}% | up {:02}:{:02}:{:02}",
+        ms(report.last_ms),
+        ms(report.mean_ms),
+        ms(report.p95_ms),
+        report.loss_pct,
+        up / 3600,
+        up / 60 % 60,
+        up % 60
+    )
+}
+
+/// Redraw the status line in place.
+pub fn redraw_oneline(report: &SummaryReport) {
+    let mut out = io::stdout().lock();
+    let _ = write!(out, "\r\x1b[2K{}", format_oneline(report));
+    let _ = out.flush();
+}
+
+/// Clear the status line so a regular line can be printed in its place;
+/// the next redraw puts the status back below it.
+pub fn clear_oneline() {
+    let mut out = io::stdout().lock();
+    let _ = write!(out, "\r\x1b[2K");
+    let _ = out.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::stats::Sample;
+
+    #[test]
+    fn oneline_shows_the_snapshot() {
+        let samples: Vec<Sample> = [10.0, 30.0, 20.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &rtt_ms)| Sample {
+                seq: i as u64,
+                at_s: i as f64,
+                rtt_ms,
+            })
+            .collect();
+        let report = SummaryReport::from_samples(&samples, 4, 1, 0, 0, Duration::from_secs(3725));
+        assert_eq!(
+            format_oneline(&report),
+            "rtt 20.00 ms | mean 20.00 ms | p95 30.00 ms | loss 25.0% | up 01:02:05"
+        );
+        let empty = SummaryReport::from_samples(&[], 0, 0, 0, 0, Duration::ZERO);
+        assert_eq!(
+            format_oneline(&empty),
+            "rtt - ms | mean - ms | p95 - ms | loss 0.0% | up 00:00:00"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Fire-and-forget export of RTT samples over UDP.
+//!
+//! Each sample is one 24-byte datagram, all fields big-endian:
+//!
+//! | bytes  | field                               |
+//! |--------|-------------------------------------|
+//! | 0..8   | seq                                 |
+//! | 8..16  | RTT in nanoseconds                  |
+//! | 16..24 | peer id given with `--peer-id`      |
+//!
+//! This is UDP: datagrams can be dropped, duplicated or reordered on the
+//! way to the collector, and nothing is retried.
+
+use anyhow::{anyhow, Context, Result};
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use crate::stats::Sample;
+
+pub const DATAGRAM_LEN: usize = 24;
+
+pub struct UdpExporter {
+    socket: UdpSocket,
+    peer_id: u64,
+}
+
+impl UdpExporter {
+    pub fn connect(target: &str, peer_id: u64) -> Result<Self> {
+        let addr = target
+            .to_socket_addrs()
+            .with_context(|| format!("resolving --udp-export {}", target))?
+            .next()
+            .ok_or_else(|| anyhow!("--udp-export {} resolved to no address", target))?;
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).context("binding the export socket")?;
+        socket.connect(addr)?;
+        // Never let a slow collector hold up the receive path
+        socket.set_nonblocking(true)?;
+        Ok(UdpExporter { socket, peer_id })
+    }
+
+    pub fn encode(sample: &Sample, peer_id: u64) -> [u8; DATAGRAM_LEN] {
+        let mut buf = [0u8; DATAGRAM_LEN];
+        buf[0..8].copy_from_slice(&sample.seq.to_be_bytes());
+        buf[8..16].copy_from_slice(&((sample.rtt_ms * 1e6) as u64).to_be_bytes());
+        buf[16..24].copy_from_slice(&peer_id.to_be_bytes());
+        buf
+    }
+
+    /// Send a sample. Failures, including ICMP errors reported on a later
+    /// send, are ignored.
+    pub fn send(&self, sample: &Sample) {
+        let _ = self.socket.send(&Self::encode(sample, self.peer_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn datagrams_reach_a_local_collector() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let target = collector.local_addr().unwrap().to_string();
+        let exporter = UdpExporter::connect(&target, 0x0102_0304_0506_0708).unwrap();
+        exporter.send(&Sample {
+            seq: 258,
+            at_s: 1.0,
+            rtt_ms: 1.5,
+        });
+
+        let mut buf = [0u8; 64];
+        let n = collector.recv(&mut buf).unwrap();
+        assert_eq!(n, DATAGRAM_LEN);
+        assert_eq!(
+            buf[..n],
+            [
+                0, 0, 0, 0, 0, 0, 1, 2, // seq 258
+                0, 0, 0, 0, 0, 0x16, 0xe3, 0x60, // 1_500_000 ns
+                1, 2, 3, 4, 5, 6, 7, 8, // peer id
+            ]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Run-ending errors classified for --error-json.
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::path::Path;
+
+use crate::cli::CommonArgs;
+
+/// What went wrong, for automation to branch on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// The connection was not up within --connect-timeout
+    ConnectTimeout,
+    /// The offer was handed an offer instead of an answer
+    BothOfferers,
+    /// The answer was handed an answer instead of an offer
+    BothAnswerers,
+    /// A pasted blob did not decode to a session description
+    SdpParse,
+    /// The local SDP fell short of --min-candidates or --require-srflx
+    Candidates,
+    /// The peer's wire format was refused (--strict-seq)
+    FormatMismatch,
+    /// The peers' candidates share no IP version, so no pair can form
+    AddressFamily,
+    /// The connectivity probe went unanswered
+    NoConnectivity,
+    /// The peer stopped answering keepalives
+    Liveness,
+    /// The data channel reported an error
+    ChannelError,
+    /// Anything not classified above
+    Other,
+}
+
+/// A classified error. It travels inside `anyhow::Error` like any other and
+/// is found again anywhere in the chain, so added context does not hide it.
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: FailureKind,
+    message: String,
+    context: Map<String, Value>,
+}
+
+impl Failure {
+    pub fn new(kind: FailureKind, message: impl Into<String>) -> Self {
+        Failure {
+            kind,
+            message: message.into(),
+            context: Map::new(),
+        }
+    }
+
+    /// Attach a context field to the JSON report.
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.context.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The JSON object describing a failed run.
+pub fn to_json(err: &anyhow::Error) -> Value {
+    let failure = err.chain().find_map(|e| e.downcast_ref::<Failure>());
+    serde_json::json!({
+        "kind": failure.map_or(FailureKind::Other, |f| f.kind),
+        "message": format!("{:#}", err),
+        "context": failure.map(|f| f.context.clone()).unwrap_or_default(),
+    })
+}
+
+/// Hand back the outcome of a run, reporting a failure as JSON first when
+/// asked to. With --error-file the JSON is written there; with --error-json
+/// it replaces the usual text on stderr and the process exits with status
+/// 1. Either, both or neither may be set.
+pub fn finish(outcome: Result<()>, args: &CommonArgs) -> Result<()> {
+    let Err(err) = &outcome else {
+        return outcome;
+    };
+    let line = to_json(err).to_string();
+    if let Some(path) = &args.error_file {
+        if let Err(e) = write(path, &line) {
+            eprintln!("warning: cannot write {}: {:#}", path.display(), e);
+        }
+    }
+    if args.error_json {
+        eprintln!("{}", line);
+        std::process::exit(1);
+    }
+    outcome
+}
+
+fn write(path: &Path, line: &str) -> Result<()> {
+    std::fs::write(path, format!("{}\n", line))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+    use webrtc::api::APIBuilder;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+
+    use crate::peer;
+
+    #[tokio::test]
+    async fn expired_connect_deadline_is_a_connect_timeout() {
+        let api = APIBuilder::new().build();
+        let pc = Arc::new(
+            api.new_peer_connection(RTCConfiguration::default())
+                .await
+                .unwrap(),
+        );
+        let (fail_tx, mut fail_rx) = mpsc::channel(1);
+        let limit = Duration::from_millis(50);
+        peer::connect_deadline(
+            Arc::downgrade(&pc),
+            limit,
+            fail_tx,
+            CancellationToken::new(),
+        )
+        .await;
+        let err = fail_rx
+            .try_recv()
+            .expect("a never connected peer must time out");
+        let report = to_json(&err);
+        assert_eq!(report["kind"], "connect_timeout");
+        assert_eq!(report["context"]["state"], "new");
+        pc.close().await.unwrap();
+    }
+
+    #[test]
+    fn kind_survives_added_context() {
+        let err = anyhow::Error::from(Failure::new(FailureKind::SdpParse, "bad blob"))
+            .context("reading the answer");
+        let report = to_json(&err);
+        assert_eq!(report["kind"], "sdp_parse");
+        assert_eq!(report["message"], "reading the answer: bad blob");
+        let plain = to_json(&anyhow::anyhow!("boom"));
+        assert_eq!(plain["kind"], "other");
+    }
+}
@@ -0,0 +1,308 @@
+//! External command run on threshold events (--on-breach).
+//!
+//! The template is split into words once, shell-style (single and double
+//! quotes, backslash escapes), and each event runs the result directly,
+//! with no shell in between, so a substituted value is always exactly one
+//! argument and cannot inject commands. Placeholders:
+//!
+//! | placeholder   | value                                        |
+//! |---------------|----------------------------------------------|
+//! | `{event}`     | `rtt` or `loss`                              |
+//! | `{seq}`       | the slow ping, or the first lost one         |
+//! | `{last}`      | the last lost ping (`{seq}` for rtt events)  |
+//! | `{rtt}`       | RTT in ms, `-` for loss events               |
+//! | `{threshold}` | --breach-rtt in ms, `-` when unset           |
+//!
+//! At most one command runs at a time and a new one starts no sooner than
+//! the cooldown after the last; events in between are dropped and counted.
+//! Commands are waited for on the run's task tracker, so shutdown waits
+//! for one still running rather than report it after the summary.
+
+use anyhow::{bail, Result};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio_util::task::TaskTracker;
+
+const PLACEHOLDERS: [&str; 5] = ["event", "seq", "last", "rtt", "threshold"];
+
+/// A threshold event.
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    /// A ping's RTT exceeded --breach-rtt
+    Rtt { seq: u64, rtt_ms: f64 },
+    /// Pings `first..=last` were counted lost
+    Loss { first: u64, last: u64 },
+}
+
+pub struct Hook {
+    argv: Vec<String>,
+    threshold_ms: Option<u64>,
+    cooldown: Duration,
+    last_run: Mutex<Option<Instant>>,
+    running: Arc<AtomicBool>,
+    suppressed: AtomicU64,
+    tasks: TaskTracker,
+}
+
+impl Hook {
+    pub fn new(
+        template: &str,
+        threshold_ms: Option<u64>,
+        cooldown: Duration,
+        tasks: TaskTracker,
+    ) -> Result<Self> {
+        let argv = split(template)?;
+        if argv.is_empty() {
+            bail!("--on-breach needs a command");
+        }
+        for word in &argv {
+            check_placeholders(word)?;
+        }
+        Ok(Hook {
+            argv,
+            threshold_ms,
+            cooldown,
+            last_run: Mutex::new(None),
+            running: Arc::new(AtomicBool::new(false)),
+            suppressed: AtomicU64::new(0),
+            tasks,
+        })
+    }
+
+    /// Report an RTT sample, running the hook if it breaches the threshold.
+    pub fn on_rtt(&self, seq: u64, rtt_ms: f64) {
+        if self.threshold_ms.is_some_and(|t| rtt_ms > t as f64) {
+            self.fire(Event::Rtt { seq, rtt_ms });
+        }
+    }
+
+    /// Run the command for `event` unless the rate limit holds it back.
+    /// Must be called within the tokio runtime.
+    pub fn fire(&self, event: Event) {
+        let now = Instant::now();
+        {
+            let mut last_run = self.last_run.lock().unwrap();
+            let cooling = last_run.is_some_and(|at| now.duration_since(at) < self.cooldown);
+            if cooling || self.running.swap(true, Ordering::SeqCst) {
+                self.suppressed.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            *last_run = Some(now);
+        }
+        let argv = self.argv(&event);
+        let child = Command::new(&argv[0])
+            .args(&argv[1..])
+            .stdin(Stdio::null())
+            .spawn();
+        let running = Arc::clone(&self.running);
+        match child {
+            Ok(mut child) => {
+                self.tasks.spawn(async move {
+                    match child.wait().await {
+                        Ok(status) if !status.success() => {
+                            eprintln!("warning: --on-breach command exited with {}", status)
+                        }
+                        Ok(_) => {}
+                        Err(e) => eprintln!("warning: --on-breach command: {}", e),
+                    }
+                    running.store(false, Ordering::SeqCst);
+                });
+            }
+            Err(e) => {
+                eprintln!("warning: cannot run --on-breach {:?}: {}", argv[0], e);
+                running.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Events dropped by the rate limit so far.
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+
+    /// The command line for `event`, placeholders substituted.
+    fn argv(&self, event: &Event) -> Vec<String> {
+        self.argv
+            .iter()
+            .map(|word| self.substitute(word, event))
+            .collect()
+    }
+
+    fn substitute(&self, word: &str, event: &Event) -> String {
+        let (name, seq, last, rtt) = match *event {
+            Event::Rtt { seq, rtt_ms } => ("rtt", seq, seq, format!("{:.2}", rtt_ms)),
+            Event::Loss { first, last } => ("loss", first, last, "-".to_string()),
+        };
+        let threshold = self
+            .threshold_ms
+            .map_or_else(|| "-".to_string(), |t| t.to_string());
+        word.replace("{event}", name)
+            .replace("{seq}", &seq.to_string())
+            .replace("{last}", &last.to_string())
+            .replace("{rtt}", &rtt)
+            .replace("{threshold}", &threshold)
+    }
+}
+
+/// Refuse unknown `{...}` placeholders rather than pass them on literally.
+fn check_placeholders(word: &str) -> Result<()> {
+    let mut rest = word;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 1..start + len];
+        if !PLACEHOLDERS.contains(&name) {
+            bail!(
+                "unknown --on-breach placeholder {{{}}}, expected one of {}",
+                name,
+                PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(" ")
+            );
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(())
+}
+
+/// Split a command line into words the way a POSIX shell would, without
+/// expanding anything.
+fn split(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated ' in --on-breach"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => bail!("unterminated \" in --on-breach"),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated \" in --on-breach"),
+                    }
+                }
+            }
+            '\\' => {
+                let Some(c) = chars.next() else {
+                    bail!("trailing \\ in --on-breach");
+                };
+                word.get_or_insert_with(String::new).push(c);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(template: &str, threshold_ms: Option<u64>) -> Hook {
+        Hook::new(
+            template,
+            threshold_ms,
+            Duration::from_secs(10),
+            TaskTracker::new(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn splits_like_a_shell() {
+        let words = split(r#"notify 'rtt {rtt} ms' "seq \"{seq}\"" a\ b  c"#).unwrap();
+        assert_eq!(
+            words,
+            ["notify", "rtt {rtt} ms", "seq \"{seq}\"", "a b", "c"]
+        );
+        assert_eq!(split("x ''").unwrap(), ["x", ""]);
+        assert!(split("echo 'open").is_err());
+        assert!(split("echo \"open").is_err());
+        assert!(split("echo \\").is_err());
+    }
+
+    #[test]
+    fn unknown_placeholders_are_refused() {
+        for word in ["{seq}-{last}", "{event}{rtt}{threshold}", "{", "a}b"] {
+            assert!(check_placeholders(word).is_ok(), "{}", word);
+        }
+        let err = check_placeholders("{ip}").unwrap_err().to_string();
+        assert!(err.contains("{ip}") && err.contains("{seq}"), "{}", err);
+        assert!(Hook::new("echo {rttt}", None, Duration::ZERO, TaskTracker::new()).is_err());
+        assert!(Hook::new("  ", None, Duration::ZERO, TaskTracker::new()).is_err());
+    }
+
+    #[test]
+    fn argv_for_a_simulated_breach() {
+        let hook = hook(
+            "alert 'rtt {rtt} ms > {threshold}' --seq={seq} {event}",
+            Some(50),
+        );
+        let rtt = Event::Rtt {
+            seq: 42,
+            rtt_ms: 61.234,
+        };
+        assert_eq!(
+            hook.argv(&rtt),
+            ["alert", "rtt 61.23 ms > 50", "--seq=42", "rtt"]
+        );
+        let loss = Event::Loss { first: 7, last: 9 };
+        assert_eq!(
+            hook.argv(&loss),
+            ["alert", "rtt - ms > 50", "--seq=7", "loss"]
+        );
+    }
+
+    #[test]
+    fn substituted_values_stay_one_argument() {
+        let hook = hook("printf %s {rtt}; rm -rf /", None);
+        let argv = hook.argv(&Event::Rtt {
+            seq: 1,
+            rtt_ms: 1.0,
+        });
+        assert_eq!(argv, ["printf", "%s", "1.00;", "rm", "-rf", "/"]);
+    }
+
+    #[tokio::test]
+    async fn breach_runs_the_command_once_per_cooldown() {
+        let out = std::env::temp_dir().join(format!("hook-test-{}", std::process::id()));
+        let template = format!(
+            "sh -c 'printf \"%s\\n\" \"$@\" > {}' hook {{event}} {{seq}} {{rtt}}",
+            out.display()
+        );
+        let tasks = TaskTracker::new();
+        let hook = Hook::new(&template, Some(10), Duration::from_secs(60), tasks.clone()).unwrap();
+        hook.on_rtt(1, 5.0);
+        hook.on_rtt(2, 12.5);
+        hook.on_rtt(3, 20.0);
+        tasks.close();
+        tasks.wait().await;
+        let written = std::fs::read_to_string(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        assert_eq!(written, "rtt\n2\n12.50\n");
+        assert_eq!(hook.suppressed(), 1);
+    }
+}
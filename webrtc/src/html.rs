@@ -0,0 +1,264 @@
+//! Self-contained HTML report for --html: a summary table, RTT over time
+//! and an RTT histogram. The charts are inline SVG drawn here, so the file
+//! needs no scripts, stylesheets or server to open.
+
+use anyhow::{Context, Result};
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::stats::{ms, Sample, SummaryReport};
+
+const WIDTH: f64 = 720.0;
+const HEIGHT: f64 = 240.0;
+/// Room left of and below the plot for axis labels.
+const MARGIN: f64 = 48.0;
+const BINS: usize = 30;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse}td,th{padding:4px 12px;border-bottom:1px solid #ddd;\
+text-align:left}td.n{text-align:right;font-variant-numeric:tabular-nums}\
+svg{display:block;margin:1em 0}text{font-size:11px;fill:#555}";
+
+/// Render the report for a finished run.
+pub fn render(title: &str, report: &SummaryReport, samples: &[Sample]) -> String {
+    let mut out = String::new();
+    let title = escape(title);
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        title, STYLE, title
+    );
+    summary_table(&mut out, report);
+    out.push_str("<h2>RTT over time</h2>\n");
+    rtt_chart(&mut out, samples);
+    out.push_str("<h2>RTT histogram</h2>\n");
+    histogram(&mut out, samples);
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Render the report and write it to `path`.
+pub fn write(path: &Path, title: &str, report: &SummaryReport, samples: &[Sample]) -> Result<()> {
+    std::fs::write(path, render(title, report, samples))
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+fn summary_table(out: &mut String, r: &SummaryReport) {
+    let rows = [
+        ("sent", r.sent.to_string()),
+        ("received", r.received.to_string()),
+        ("lost", format!("{} ({:.1}%)", r.lost, r.loss_pct)),
+        ("late", r.late.to_string()),
+        ("in flight", r.in_flight.to_string()),
+        ("min RTT (ms)", ms(r.min_ms)),
+        ("mean RTT (ms)", ms(r.mean_ms)),
+        (
+            "mean 95% CI (ms)",
+            r.mean_ci_ms
+                .map_or_else(|| "-".to_string(), |i| i.to_string()),
+        ),
+        ("p50 RTT (ms)", ms(r.p50_ms)),
+        ("p95 RTT (ms)", ms(r.p95_ms)),
+        ("p99 RTT (ms)", ms(r.p99_ms)),
+        ("max RTT (ms)", ms(r.max_ms)),
+        ("jitter (ms)", ms(r.jitter_ms)),
+        ("duration (s)", format!("{:.1}", r.uptime_s)),
+    ];
+    out.push_str("<h2>Summary</h2>\n<table>\n");
+    for (name, value) in rows {
+        let _ = writeln!(
+            out,
+            "<tr><th>{}</th><td class=\"n\">{}</td></tr>",
+            name, value
+        );
+    }
+    out.push_str("</table>\n");
+}
+
+fn open_svg(out: &mut String, label: &str) {
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+         viewBox=\"0 0 {w} {h}\" role=\"img\" aria-label=\"{label}\">",
+        w = WIDTH + MARGIN,
+        h = HEIGHT + MARGIN,
+        label = label
+    );
+    let _ = writeln!(
+        out,
+        "<path d=\"M{m} 0V{h}H{r}\" fill=\"none\" stroke=\"#999\"/>",
+        m = MARGIN,
+        h = HEIGHT,
+        r = WIDTH + MARGIN
+    );
+}
+
+/// Label the axes with their ranges.
+fn axis_labels(out: &mut String, x: (&str, &str), y_max: &str) {
+    let _ = writeln!(
+        out,
+        "<text x=\"{m}\" y=\"{b}\">{}</text>\
+         <text x=\"{r}\" y=\"{b}\" text-anchor=\"end\">{}</text>\
+         <text x=\"{l}\" y=\"12\" text-anchor=\"end\">{}</text>\
+         <text x=\"{l}\" y=\"{h}\" text-anchor=\"end\">0</text>",
+        x.0,
+        x.1,
+        y_max,
+        m = MARGIN,
+        r = WIDTH + MARGIN,
+        b = HEIGHT + 16.0,
+        l = MARGIN - 4.0,
+        h = HEIGHT
+    );
+}
+
+fn no_samples(out: &mut String) {
+    out.push_str("<p>No RTT samples were collected.</p>\n");
+}
+
+fn rtt_chart(out: &mut String, samples: &[Sample]) {
+    if samples.is_empty() {
+        return no_samples(out);
+    }
+    let t0 = samples.iter().map(|s| s.at_s).fold(f64::INFINITY, f64::min);
+    let t1 = samples.iter().map(|s| s.at_s).fold(t0, f64::max);
+    let top = samples
+        .iter()
+        .map(|s| s.rtt_ms)
+        .fold(0.0, f64::max)
+        .max(1e-3);
+    let span = (t1 - t0).max(1e-3);
+    open_svg(out, "RTT over time");
+    out.push_str("<polyline fill=\"none\" stroke=\"#2a6fdb\" stroke-width=\"1\" points=\"");
+    for s in samples {
+        let x = MARGIN + (s.at_s - t0) / span * WIDTH;
+        let y = HEIGHT - s.rtt_ms / top * HEIGHT;
+        let _ = write!(out, "{:.1},{:.1} ", x, y);
+    }
+    out.push_str("\"/>\n");
+    axis_labels(
+        out,
+        (&format!("{:.1} s", t0), &format!("{:.1} s", t1)),
+        &format!("{:.2} ms", top),
+    );
+    out.push_str("</svg>\n");
+}
+
+fn histogram(out: &mut String, samples: &[Sample]) {
+    if samples.is_empty() {
+        return no_samples(out);
+    }
+    let lo = samples
+        .iter()
+        .map(|s| s.rtt_ms)
+        .fold(f64::INFINITY, f64::min);
+    let hi = samples.iter().map(|s| s.rtt_ms).fold(lo, f64::max);
+    let width = ((hi - lo) / BINS as f64).max(1e-3);
+    let mut counts = [0u64; BINS];
+    for s in samples {
+        let bin = ((s.rtt_ms - lo) / width) as usize;
+        counts[bin.min(BINS - 1)] += 1;
+    }
+    let top = counts.iter().copied().max().unwrap_or(1) as f64;
+    let bar = WIDTH / BINS as f64;
+    open_svg(out, "RTT histogram");
+    for (i, &count) in counts.iter().enumerate() {
+        let h = count as f64 / top * HEIGHT;
+        let from = lo + i as f64 * width;
+        let _ = writeln!(
+            out,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#2a6fdb\">\
+             <title>{:.2}-{:.2} ms: {}</title></rect>",
+            MARGIN + i as f64 * bar + 1.0,
+            HEIGHT - h,
+            bar - 2.0,
+            h,
+            from,
+            from + width,
+            count
+        );
+    }
+    axis_labels(
+        out,
+        (&format!("{:.2} ms", lo), &format!("{:.2} ms", hi)),
+        &top.to_string(),
+    );
+    out.push_str("</svg>\n");
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Stats;
+    use std::time::Duration;
+
+    /// Ten pings a second apart, RTTs 10 to 19 ms, the fourth never echoed.
+    fn run() -> (SummaryReport, Vec<Sample>) {
+        let mut stats = Stats::new();
+        let mut samples = Vec::new();
+        for seq in 0..10 {
+            let sent = Duration::from_secs(seq);
+            stats.on_sent(seq, sent);
+            if seq != 3 {
+                let now = sent + Duration::from_millis(10 + seq);
+                samples.extend(stats.on_echo(seq, sent, now));
+            }
+        }
+        (stats.summary(Duration::from_secs(12)), samples)
+    }
+
+    /// Check that every element is closed, in order. Void and
+    /// self-closing elements need no end tag.
+    fn assert_well_formed(html: &str) {
+        let mut open: Vec<&str> = Vec::new();
+        let mut rest = html.strip_prefix("<!DOCTYPE html>").expect("doctype");
+        while let Some(start) = rest.find('<') {
+            let end = start + rest[start..].find('>').expect("unclosed tag");
+            let tag = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+            assert!(!tag.contains('<'), "stray < in {:?}", tag);
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name), "mismatched </{}>", name);
+            } else if !tag.ends_with('/') && tag != "meta charset=\"utf-8\"" {
+                open.push(tag.split(' ').next().unwrap());
+            }
+        }
+        assert!(open.is_empty(), "never closed: {:?}", open);
+    }
+
+    #[test]
+    fn report_shows_the_summary_in_well_formed_html() {
+        let (report, samples) = run();
+        let html = render("Run <a & b>", &report, &samples);
+        assert_well_formed(&html);
+        assert!(html.contains("<title>Run &lt;a &amp; b&gt;</title>"));
+        for (name, value) in [
+            ("sent", "10"),
+            ("received", "9"),
+            ("min RTT (ms)", "10.00"),
+            ("mean RTT (ms)", "14.67"),
+            ("max RTT (ms)", "19.00"),
+            ("duration (s)", "12.0"),
+        ] {
+            let row = format!("<tr><th>{}</th><td class=\"n\">{}</td></tr>", name, value);
+            assert!(html.contains(&row), "{:?} missing from\n{}", row, html);
+        }
+        // One point per sample and a bar per bin
+        let points = html.split("points=\"").nth(1).unwrap();
+        let points = &points[..points.find('"').unwrap()];
+        assert_eq!(points.split_whitespace().count(), samples.len());
+        assert_eq!(html.matches("<rect ").count(), BINS);
+
+        let empty = render("empty", &Stats::new().summary(Duration::ZERO), &[]);
+        assert_well_formed(&empty);
+        assert_eq!(empty.matches("No RTT samples were collected.").count(), 2);
+    }
+}
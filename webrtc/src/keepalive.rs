@@ -0,0 +1,138 @@
+//! Application-level liveness checks (--keepalive), separate from the
+//! latency pings and from ICE consent: one side sends keepalive pings on
+//! the channel, the other answers each with a pong, and the sender gives
+//! the connection up once no pong has come back for the timeout.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Instant};
+use tokio_util::sync::CancellationToken;
+use webrtc::data_channel::RTCDataChannel;
+
+use crate::cli::CommonArgs;
+use crate::failure::{Failure, FailureKind};
+use crate::wire::Frame;
+
+/// Keepalive state shared between the sender and the message handler.
+#[derive(Debug)]
+pub struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    /// When the last pong arrived, or the sender started
+    last_pong: Mutex<Instant>,
+}
+
+impl Keepalive {
+    /// The keepalive --keepalive asks for, if any.
+    pub fn from_args(args: &CommonArgs) -> Option<Arc<Self>> {
+        let interval = Duration::from_secs(args.keepalive?);
+        Some(Arc::new(Keepalive {
+            interval,
+            timeout: Duration::from_secs(args.keepalive_timeout),
+            last_pong: Mutex::new(Instant::now()),
+        }))
+    }
+
+    pub fn on_pong(&self) {
+        *self.last_pong.lock().unwrap() = Instant::now();
+    }
+
+    /// Send keepalive pings until cancelled, failing the connection through
+    /// `fail_tx` once the peer has not answered for the timeout.
+    pub async fn run(
+        self: Arc<Self>,
+        dc: Arc<RTCDataChannel>,
+        fail_tx: mpsc::Sender<anyhow::Error>,
+        cancel: CancellationToken,
+    ) {
+        self.on_pong();
+        let mut nonce = 0;
+        loop {
+            let silent = self.last_pong.lock().unwrap().elapsed();
+            if silent >= self.timeout {
+                let message = format!(
+                    "peer stopped answering keepalives: no pong for {:.1}s (--keepalive-timeout {})",
+                    silent.as_secs_f64(),
+                    self.timeout.as_secs()
+                );
+                let failure = Failure::new(FailureKind::Liveness, message)
+                    .with("timeout_s", self.timeout.as_secs());
+                let _ = fail_tx.send(failure.into()).await;
+                return;
+            }
+            if let Err(e) = dc.send(&Frame::KeepalivePing { nonce }.encode()).await {
+                eprintln!("keepalive send error: {:?}", e);
+            }
+            nonce += 1;
+            // Wake in time to notice the timeout even with a long interval
+            let wait = self.interval.min(self.timeout - silent);
+            tokio::select! {
+                _ = sleep(wait) => {}
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failure;
+    use crate::testdata;
+    use webrtc::api::APIBuilder;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+
+    /// A channel that never opens, so no keepalive reaches a peer.
+    async fn unanswered_channel() -> Arc<RTCDataChannel> {
+        let pc = APIBuilder::new()
+            .build()
+            .new_peer_connection(RTCConfiguration::default())
+            .await
+            .unwrap();
+        pc.create_data_channel("keepalive", None).await.unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn missed_pongs_fail_the_connection_at_the_timeout() {
+        let args = testdata::common_args(&["--keepalive", "1", "--keepalive-timeout", "3"]);
+        let keepalive = Keepalive::from_args(&args).unwrap();
+        let (fail_tx, mut fail_rx) = mpsc::channel(1);
+        let cancel = CancellationToken::new();
+        let start = Instant::now();
+        tokio::spawn(Arc::clone(&keepalive).run(unanswered_channel().await, fail_tx, cancel));
+
+        // Answered for a while, then silent
+        for _ in 0..5 {
+            sleep(Duration::from_millis(900)).await;
+            keepalive.on_pong();
+        }
+        assert!(fail_rx.try_recv().is_err());
+        let answered = start.elapsed();
+
+        let err = fail_rx.recv().await.unwrap();
+        assert_eq!(start.elapsed() - answered, Duration::from_secs(3));
+        let json = failure::to_json(&err);
+        assert_eq!(json["kind"], "liveness");
+        assert_eq!(json["context"]["timeout_s"], 3);
+        assert_eq!(
+            err.to_string(),
+            "peer stopped answering keepalives: no pong for 3.0s (--keepalive-timeout 3)"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_stops_the_sender() {
+        let args = testdata::common_args(&["--keepalive", "1"]);
+        let keepalive = Keepalive::from_args(&args).unwrap();
+        let (fail_tx, mut fail_rx) = mpsc::channel(1);
+        let cancel = CancellationToken::new();
+        let sender =
+            tokio::spawn(keepalive.run(unanswered_channel().await, fail_tx, cancel.clone()));
+        sleep(Duration::from_secs(5)).await;
+        cancel.cancel();
+        sender.await.unwrap();
+        // The sender dropped its end without reporting a failure
+        assert!(fail_rx.recv().await.is_none());
+    }
+}
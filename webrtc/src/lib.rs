@@ -0,0 +1,32 @@
+pub mod aimd;
+pub mod banner;
+pub mod cert;
+pub mod checkpoint;
+pub mod ci;
+pub mod cidr;
+pub mod cli;
+pub mod control;
+pub mod display;
+pub mod export;
+pub mod failure;
+pub mod hook;
+pub mod html;
+pub mod keepalive;
+pub mod loss;
+pub mod munge;
+pub mod nat;
+pub mod oneway;
+pub mod path;
+pub mod peer;
+pub mod schedule;
+pub mod sdp;
+pub mod shutdown;
+pub mod signal;
+pub mod srflx;
+pub mod stats;
+pub mod sweep;
+pub mod trickle;
+pub mod wire;
+
+#[cfg(test)]
+mod testdata;
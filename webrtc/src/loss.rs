@@ -0,0 +1,54 @@
+//! Seeded receive-side loss for --recv-drop.
+//!
+//! Whether a ping is dropped depends only on the seed and its sequence
+//! number: ping `seq` is dropped when the `seq`-th output of a splitmix64
+//! stream seeded with `seed` falls below the drop fraction. The dropped set
+//! is the same on every run, whatever order the pings arrive in, so the
+//! offer's lost sequences can be checked against it exactly.
+
+use crate::wire;
+
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Output `index` of the splitmix64 stream seeded with `seed`.
+pub(crate) fn splitmix(seed: u64, index: u64) -> u64 {
+    mix(seed.wrapping_add(index.wrapping_add(1).wrapping_mul(GAMMA)))
+}
+
+/// Decides which received pings to drop instead of echoing.
+#[derive(Debug, Clone, Copy)]
+pub struct SeededDrop {
+    seed: u64,
+    fraction: f64,
+}
+
+impl SeededDrop {
+    pub fn new(fraction: f64, seed: u64) -> Self {
+        SeededDrop { seed, fraction }
+    }
+
+    /// Whether ping `seq` is dropped. The connectivity probe never is.
+    pub fn drops(&self, seq: u64) -> bool {
+        if seq == wire::PROBE_SEQ {
+            return false;
+        }
+        let x = splitmix(self.seed, seq);
+        // Top 53 bits as a uniform value in [0, 1)
+        ((x >> 11) as f64 / (1u64 << 53) as f64) < self.fraction
+    }
+}
+
+/// Parse a drop fraction between 0 and 1.
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    let fraction: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err("must be between 0 and 1".to_string());
+    }
+    Ok(fraction)
+}
@@ -0,0 +1,182 @@
+//! Line-based rewrites of the local SDP, for peers that choke on
+//! particular attributes.
+//!
+//! Rules are read from a JSON array and applied in order:
+//!
+//! ```json
+//! [
+//!   { "op": "remove", "prefix": "a=extmap-allow-mixed" },
+//!   { "op": "replace", "prefix": "a=max-message-size:", "line": "a=max-message-size:65536" },
+//!   { "op": "add", "line": "a=x-custom:1", "after": "m=application" }
+//! ]
+//! ```
+//!
+//! A rule matches every line starting with its prefix. `add` without
+//! `after` appends to the session section, before the first `m=` line.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Rule {
+    /// Drop matching lines
+    Remove { prefix: String },
+    /// Swap matching lines for `line`
+    Replace { prefix: String, line: String },
+    /// Insert `line` after each line starting with `after`
+    Add {
+        line: String,
+        #[serde(default)]
+        after: Option<String>,
+    },
+}
+
+impl Rule {
+    /// The prefix a line must start with for this rule to touch it, or
+    /// `None` for an `add` into the session section.
+    fn prefix(&self) -> Option<&str> {
+        match self {
+            Rule::Remove { prefix } | Rule::Replace { prefix, .. } => Some(prefix),
+            Rule::Add { after, .. } => after.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rules(Vec<Rule>);
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let rules =
+            serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        Ok(Rules(rules))
+    }
+
+    /// Apply every rule to the SDP text. Returns the result along with the
+    /// indexes of rules that matched no line.
+    pub fn apply_to_sdp(&self, sdp: &str) -> (String, Vec<usize>) {
+        let eol = if sdp.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut lines: Vec<String> = sdp.lines().map(str::to_string).collect();
+        let mut unmatched = Vec::new();
+        for (i, rule) in self.0.iter().enumerate() {
+            let matches = |l: &str| rule.prefix().is_some_and(|p| l.starts_with(p));
+            let before = lines.len();
+            let mut hits = 0;
+            lines = match rule {
+                Rule::Remove { .. } => lines
+                    .into_iter()
+                    .filter(|l| {
+                        let hit = matches(l);
+                        hits += hit as usize;
+                        !hit
+                    })
+                    .collect(),
+                Rule::Replace { line, .. } => lines
+                    .into_iter()
+                    .map(|l| {
+                        if matches(&l) {
+                            hits += 1;
+                            line.clone()
+                        } else {
+                            l
+                        }
+                    })
+                    .collect(),
+                Rule::Add { line, after: None } => {
+                    let at = lines
+                        .iter()
+                        .position(|l| l.starts_with("m="))
+                        .unwrap_or(lines.len());
+                    lines.insert(at, line.clone());
+                    hits = 1;
+                    lines
+                }
+                Rule::Add { line, .. } => {
+                    let mut out = Vec::with_capacity(before + 1);
+                    for l in lines {
+                        let hit = matches(&l);
+                        out.push(l);
+                        if hit {
+                            hits += 1;
+                            out.push(line.clone());
+                        }
+                    }
+                    out
+                }
+            };
+            if hits == 0 {
+                unmatched.push(i);
+            }
+        }
+        let mut out = lines.join(eol);
+        if !out.is_empty() {
+            out.push_str(eol);
+        }
+        (out, unmatched)
+    }
+
+    /// Rewrite a description before it is sent, and check the result still
+    /// parses as SDP.
+    pub fn apply(&self, desc: &mut RTCSessionDescription) -> Result<()> {
+        let (sdp, unmatched) = self.apply_to_sdp(&desc.sdp);
+        for i in unmatched {
+            eprintln!("warning: --sdp-munge rule {} matched no line", i + 1);
+        }
+        desc.sdp = sdp;
+        desc.unmarshal()
+            .context("SDP no longer parses after --sdp-munge")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata;
+
+    fn rules(json: &str) -> Rules {
+        Rules(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn rules_rewrite_and_the_sdp_still_parses() {
+        let rules = rules(
+            r#"[
+                { "op": "remove", "prefix": "a=end-of-candidates" },
+                { "op": "replace", "prefix": "a=setup:", "line": "a=setup:active" },
+                { "op": "add", "line": "a=max-message-size:65536", "after": "a=sctp-port:" },
+                { "op": "add", "line": "a=x-custom:1" },
+                { "op": "remove", "prefix": "a=extmap-allow-mixed" }
+            ]"#,
+        );
+        let (sdp, unmatched) = rules.apply_to_sdp(testdata::OFFER_SDP);
+        assert_eq!(unmatched, [4]);
+        assert!(!sdp.contains("a=end-of-candidates"));
+        assert!(!sdp.contains("a=setup:actpass"));
+        assert!(sdp.contains("\r\na=setup:active\r\n"));
+        assert!(sdp.contains("a=sctp-port:5000\r\na=max-message-size:65536\r\n"));
+        assert!(sdp.contains("a=group:BUNDLE 0\r\na=x-custom:1\r\nm=application"));
+        assert!(sdp.ends_with("typ host\r\n"));
+
+        let mut desc = testdata::offer();
+        rules.apply(&mut desc).unwrap();
+        assert_eq!(desc.sdp, sdp);
+        let parsed = desc.unmarshal().unwrap();
+        let media = &parsed.media_descriptions[0];
+        assert_eq!(media.attribute("setup"), Some(Some("active")));
+        assert_eq!(media.attribute("max-message-size"), Some(Some("65536")));
+    }
+
+    #[test]
+    fn rules_that_break_the_sdp_are_refused() {
+        let rules = rules(r#"[{ "op": "replace", "prefix": "m=", "line": "m=application" }]"#);
+        let err = rules.apply(&mut testdata::offer()).unwrap_err();
+        assert_eq!(err.to_string(), "SDP no longer parses after --sdp-munge");
+    }
+}
@@ -0,0 +1,453 @@
+//! Standalone NAT classification from STUN binding responses.
+//!
+//! Mapping behavior comes from asking several servers from one socket:
+//! different reflexive addresses mean the NAT maps per destination
+//! (symmetric). Filtering behavior needs a server that can answer from
+//! another address (RFC 5780 OTHER-ADDRESS, or the older CHANGED-ADDRESS):
+//! if a reply from another IP gets through it is a full cone, from another
+//! port only a restricted cone, otherwise a port-restricted cone.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::{timeout, Instant};
+use webrtc::stun::addr::MappedAddress;
+use webrtc::stun::agent::TransactionId;
+use webrtc::stun::attributes::{ATTR_CHANGED_ADDRESS, ATTR_CHANGE_REQUEST, ATTR_OTHER_ADDRESS};
+use webrtc::stun::message::{Getter, Message, BINDING_REQUEST};
+use webrtc::stun::xoraddr::XorMappedAddress;
+
+/// Servers probed when none are configured; two distinct hosts are needed
+/// to tell symmetric NATs apart.
+pub const DEFAULT_SERVERS: &[&str] = &[
+    "stun:stun.l.google.com:19302",
+    "stun:stun1.l.google.com:19302",
+];
+
+/// How long to wait for each binding response.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(800);
+
+/// Binding requests sent before a server is considered unreachable.
+const ATTEMPTS: usize = 2;
+
+const CHANGE_IP: u8 = 0x04;
+const CHANGE_PORT: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NatType {
+    /// No translation: the mapped address is our own
+    Open,
+    FullCone,
+    Restricted,
+    PortRestricted,
+    /// Endpoint-independent mapping, but no server could test filtering
+    Cone,
+    Symmetric,
+    /// No server answered at all
+    UdpBlocked,
+}
+
+impl fmt::Display for NatType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NatType::Open => "open (no NAT)",
+            NatType::FullCone => "full cone",
+            NatType::Restricted => "restricted cone",
+            NatType::PortRestricted => "port-restricted cone",
+            NatType::Cone => "cone (filtering unknown)",
+            NatType::Symmetric => "symmetric",
+            NatType::UdpBlocked => "UDP blocked",
+        })
+    }
+}
+
+/// What one server reported.
+#[derive(Debug, Clone, Serialize)]
+pub struct Mapping {
+    pub server: String,
+    pub server_addr: Option<SocketAddr>,
+    /// Our address as the server saw it, `None` without a response
+    pub mapped: Option<SocketAddr>,
+    /// Where the server can answer from instead, when it supports that
+    pub other: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NatReport {
+    pub nat_type: NatType,
+    pub local: Option<SocketAddr>,
+    pub mappings: Vec<Mapping>,
+    /// Filtering tests run and their outcome
+    pub evidence: Vec<String>,
+}
+
+impl fmt::Display for NatReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "NAT type: {}", self.nat_type)?;
+        if let Some(local) = self.local {
+            writeln!(f, "  local address {}", local)?;
+        }
+        for m in &self.mappings {
+            let addr = m
+                .server_addr
+                .map_or_else(String::new, |a| format!(" ({})", a));
+            match m.mapped {
+                Some(mapped) => writeln!(f, "  {}{} -> mapped {}", m.server, addr, mapped)?,
+                None => writeln!(f, "  {}{} -> no response", m.server, addr)?,
+            }
+        }
+        let lines: Vec<String> = self.evidence.iter().map(|e| format!("  {}", e)).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Probe `servers` (stun: URLs) and classify the NAT in front of us.
+pub async fn detect(servers: &[String]) -> Result<NatReport> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    let mut mappings = Vec::new();
+    for server in servers {
+        let server_addr = resolve(server).await;
+        let response = match server_addr {
+            Some(addr) => binding(&socket, addr, None).await,
+            None => None,
+        };
+        mappings.push(Mapping {
+            server: server.clone(),
+            server_addr,
+            mapped: response.as_ref().map(|r| r.mapped),
+            other: response.and_then(|r| r.other),
+        });
+    }
+
+    let local = match mappings.iter().find_map(|m| m.server_addr) {
+        Some(addr) => local_addr(&socket, addr).await,
+        None => None,
+    };
+    let mut report = NatReport {
+        nat_type: NatType::UdpBlocked,
+        local,
+        mappings,
+        evidence: Vec::new(),
+    };
+    let mapped: Vec<SocketAddr> = report.mappings.iter().filter_map(|m| m.mapped).collect();
+    let Some(first) = mapped.first().copied() else {
+        report.evidence.push("no STUN server answered".to_string());
+        return Ok(report);
+    };
+
+    if Some(first) == local {
+        report.nat_type = NatType::Open;
+        report
+            .evidence
+            .push("mapped address equals the local address".to_string());
+        return Ok(report);
+    }
+    if mapped.iter().any(|m| *m != first) {
+        report.nat_type = NatType::Symmetric;
+        report
+            .evidence
+            .push("mapping differs per server: address-dependent mapping".to_string());
+        return Ok(report);
+    }
+    if mapped.len() < 2 {
+        report
+            .evidence
+            .push("only one server answered, so a symmetric NAT cannot be ruled out".to_string());
+    } else {
+        report
+            .evidence
+            .push("same mapping for every server: endpoint-independent mapping".to_string());
+    }
+
+    // Filtering needs a server that can reply from another address
+    let Some((server, other)) = report
+        .mappings
+        .iter()
+        .find_map(|m| Some((m.server_addr?, m.other?)))
+    else {
+        report.nat_type = NatType::Cone;
+        report
+            .evidence
+            .push("no server advertised an alternate address to test filtering".to_string());
+        return Ok(report);
+    };
+    let from_other_ip = binding(&socket, server, Some(CHANGE_IP | CHANGE_PORT)).await;
+    if from_other_ip.is_some_and(|r| r.from.ip() != server.ip()) {
+        report.nat_type = NatType::FullCone;
+        report.evidence.push(format!(
+            "reply from {} (other IP and port) got through",
+            other
+        ));
+        return Ok(report);
+    }
+    report
+        .evidence
+        .push("reply from another IP and port was filtered".to_string());
+    let from_other_port = binding(&socket, server, Some(CHANGE_PORT)).await;
+    if from_other_port.is_some_and(|r| r.from.port() != server.port()) {
+        report.nat_type = NatType::Restricted;
+        report
+            .evidence
+            .push("reply from the same IP but another port got through".to_string());
+    } else {
+        report.nat_type = NatType::PortRestricted;
+        report
+            .evidence
+            .push("reply from the same IP but another port was filtered".to_string());
+    }
+    Ok(report)
+}
+
+/// Run the probe against the configured STUN servers and print the result.
+pub async fn run(stun: &[String], json: bool) -> Result<()> {
+    let servers: Vec<String> = if stun.is_empty() {
+        DEFAULT_SERVERS.iter().map(|s| s.to_string()).collect()
+    } else {
+        stun.to_vec()
+    };
+    let report = detect(&servers).await?;
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({ "event": "nat_type", "report": report })
+        );
+    } else {
+        println!("{}", report);
+    }
+    Ok(())
+}
+
+/// Our reflexive address as `server` sees it from `socket`.
+pub async fn mapped_address(socket: &UdpSocket, server: SocketAddr) -> Option<SocketAddr> {
+    binding(socket, server, None).await.map(|r| r.mapped)
+}
+
+struct BindingResponse {
+    mapped: SocketAddr,
+    other: Option<SocketAddr>,
+    /// Where the response came from
+    from: SocketAddr,
+}
+
+/// Send a binding request, optionally asking the server to answer from
+/// another address, and wait for the matching response.
+async fn binding(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    change: Option<u8>,
+) -> Option<BindingResponse> {
+    for _ in 0..ATTEMPTS {
+        let request = match request(change) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("stun error: {}", e);
+                return None;
+            }
+        };
+        if socket.send_to(&request.raw, server).await.is_err() {
+            return None;
+        }
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        let mut buf = [0u8; 1500];
+        while let Ok(Ok((n, from))) = timeout(
+            deadline.saturating_duration_since(Instant::now()),
+            socket.recv_from(&mut buf),
+        )
+        .await
+        {
+            let mut msg = Message::new();
+            if msg.unmarshal_binary(&buf[..n]).is_err()
+                || msg.transaction_id != request.transaction_id
+            {
+                continue;
+            }
+            return parse_response(&msg, from);
+        }
+    }
+    None
+}
+
+fn request(change: Option<u8>) -> Result<Message> {
+    let mut msg = Message::new();
+    msg.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])
+        .map_err(|e| anyhow!("{}", e))?;
+    if let Some(flags) = change {
+        msg.add(ATTR_CHANGE_REQUEST, &[0, 0, 0, flags]);
+    }
+    Ok(msg)
+}
+
+fn parse_response(msg: &Message, from: SocketAddr) -> Option<BindingResponse> {
+    let mut xor = XorMappedAddress::default();
+    let mapped = if xor.get_from(msg).is_ok() {
+        SocketAddr::new(xor.ip, xor.port)
+    } else {
+        let mut plain = MappedAddress::default();
+        plain.get_from(msg).ok()?;
+        SocketAddr::new(plain.ip, plain.port)
+    };
+    let other = [ATTR_OTHER_ADDRESS, ATTR_CHANGED_ADDRESS]
+        .into_iter()
+        .find_map(|t| {
+            let mut addr = MappedAddress::default();
+            addr.get_from_as(msg, t).ok()?;
+            Some(SocketAddr::new(addr.ip, addr.port))
+        });
+    Some(BindingResponse {
+        mapped,
+        other,
+        from,
+    })
+}
+
+/// Resolve a stun: URL to its first IPv4 address.
+pub async fn resolve(url: &str) -> Option<SocketAddr> {
+    let host = url.strip_prefix("stun:").unwrap_or(url);
+    let host = host.split('?').next()?;
+    let target = match host.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+        _ => format!("{}:3478", host),
+    };
+    lookup_host(target).await.ok()?.find(SocketAddr::is_ipv4)
+}
+
+/// Our own address on the route to `remote`, with the probe socket's port.
+async fn local_addr(socket: &UdpSocket, remote: SocketAddr) -> Option<SocketAddr> {
+    let route = UdpSocket::bind("0.0.0.0:0").await.ok()?;
+    route.connect(remote).await.ok()?;
+    let ip: IpAddr = route.local_addr().ok()?.ip();
+    Some(SocketAddr::new(ip, socket.local_addr().ok()?.port()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::stun::message::BINDING_SUCCESS;
+
+    /// A local STUN server whose answers stand in for a NAT: it reports
+    /// `mapped` (or the real source) and answers change requests only when
+    /// that kind of reply would get through.
+    #[derive(Clone, Copy, Default)]
+    struct MockStun {
+        mapped: Option<SocketAddr>,
+        advertise_other: bool,
+        other_ip_passes: bool,
+        other_port_passes: bool,
+        silent: bool,
+    }
+
+    impl MockStun {
+        async fn start(self) -> String {
+            let main = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let other_port = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let other_ip = UdpSocket::bind("127.0.0.2:0").await.unwrap();
+            let url = format!("stun:{}", main.local_addr().unwrap());
+            let other = other_ip.local_addr().unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1500];
+                while let Ok((n, from)) = main.recv_from(&mut buf).await {
+                    let mut request = Message::new();
+                    if self.silent || request.unmarshal_binary(&buf[..n]).is_err() {
+                        continue;
+                    }
+                    let change = request.get(ATTR_CHANGE_REQUEST).map_or(0, |v| v[3]);
+                    let reply_from = if change & CHANGE_IP != 0 {
+                        self.other_ip_passes.then_some(&other_ip)
+                    } else if change & CHANGE_PORT != 0 {
+                        self.other_port_passes.then_some(&other_port)
+                    } else {
+                        Some(&main)
+                    };
+                    let Some(socket) = reply_from else { continue };
+                    let mapped = self.mapped.unwrap_or(from);
+                    let mut response = Message::new();
+                    response
+                        .build(&[
+                            Box::new(request.transaction_id),
+                            Box::new(BINDING_SUCCESS),
+                            Box::new(XorMappedAddress {
+                                ip: mapped.ip(),
+                                port: mapped.port(),
+                            }),
+                        ])
+                        .unwrap();
+                    if self.advertise_other {
+                        let addr = MappedAddress {
+                            ip: other.ip(),
+                            port: other.port(),
+                        };
+                        addr.add_to_as(&mut response, ATTR_OTHER_ADDRESS).unwrap();
+                    }
+                    let _ = socket.send_to(&response.raw, from).await;
+                }
+            });
+            url
+        }
+    }
+
+    const NATTED: MockStun = MockStun {
+        mapped: Some(SocketAddr::new(
+            IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 5)),
+            40000,
+        )),
+        advertise_other: true,
+        other_ip_passes: false,
+        other_port_passes: false,
+        silent: false,
+    };
+
+    async fn classify(servers: &[MockStun]) -> NatReport {
+        let mut urls = Vec::new();
+        for server in servers {
+            urls.push(server.start().await);
+        }
+        detect(&urls).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn each_nat_behavior_is_classified() {
+        let other_mapping = MockStun {
+            mapped: Some("203.0.113.5:40001".parse().unwrap()),
+            ..NATTED
+        };
+        let no_other = MockStun {
+            advertise_other: false,
+            ..NATTED
+        };
+        let cases = [
+            (vec![MockStun::default()], NatType::Open),
+            (vec![NATTED, other_mapping], NatType::Symmetric),
+            (vec![no_other, no_other], NatType::Cone),
+            (
+                vec![MockStun {
+                    other_ip_passes: true,
+                    ..NATTED
+                }],
+                NatType::FullCone,
+            ),
+            (
+                vec![MockStun {
+                    other_port_passes: true,
+                    ..NATTED
+                }],
+                NatType::Restricted,
+            ),
+            (vec![NATTED, NATTED], NatType::PortRestricted),
+            (
+                vec![MockStun {
+                    silent: true,
+                    ..NATTED
+                }],
+                NatType::UdpBlocked,
+            ),
+        ];
+        for (servers, expected) in cases {
+            let report = classify(&servers).await;
+            assert_eq!(report.nat_type, expected, "{}", report);
+        }
+    }
+}
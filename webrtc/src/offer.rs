@@ -1,83 +1,1531 @@
-use anyhow::Result;
-use bytes::Bytes;
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde::Serialize;
 use std::io::{self, Write};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::time::sleep;
-use webrtc::api::media_engine::MediaEngine;
-use webrtc::api::APIBuilder;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
 use webrtc::data_channel::data_channel_message::DataChannelMessage;
-use webrtc::ice_transport::ice_server::RTCIceServer;
-use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc_latency::aimd::Aimd;
+use webrtc_latency::banner::{Banner, Timeline};
+use webrtc_latency::checkpoint::Checkpoint;
+use webrtc_latency::ci;
+use webrtc_latency::cli::{self, CommonArgs};
+use webrtc_latency::export::UdpExporter;
+use webrtc_latency::failure::{self, Failure, FailureKind};
+use webrtc_latency::hook::{Event, Hook};
+use webrtc_latency::keepalive::Keepalive;
+use webrtc_latency::oneway::{ClockSync, OneWay, Stamp};
+use webrtc_latency::schedule::Schedule;
+use webrtc_latency::stats::{ms, Gap, Sample, Stats, SummaryReport};
+use webrtc_latency::sweep::{self, PayloadSweep, SweepRow};
+use webrtc_latency::wire::{self, FormatDescriptor, Frame};
+use webrtc_latency::{
+    control, display, html, nat, path, peer, sdp, shutdown, signal, srflx, trickle,
+};
+
+/// How long to wait for the peer's format descriptor once the channel opens.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long the connectivity probe may go unanswered.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Gap between probe retries, in case one is dropped on an unreliable channel.
+const CONFIRM_RETRY: Duration = Duration::from_millis(500);
+
+/// How long a replay or bounded run waits for the echoes of its last pings.
+const DRAIN: Duration = Duration::from_secs(1);
+
+/// Label of the extra channel opened by --compare-reliability.
+const UNRELIABLE_LABEL: &str = "latency-unreliable";
+
+/// Time between pings within a sweep step.
+const SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a sweep step waits for its last echoes.
+const SWEEP_DRAIN: Duration = Duration::from_secs(1);
+
+/// Measure data channel round-trip latency to an answering peer.
+#[derive(Parser, Serialize)]
+#[command(name = "offer")]
+struct Args {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// Subprotocol announced on the data channel
+    #[arg(long)]
+    protocol: Option<String>,
+
+    /// Let the channel deliver messages out of order
+    #[arg(long)]
+    unordered: bool,
+
+    /// Give up on a message after this many retransmissions
+    #[arg(long)]
+    max_retransmits: Option<u16>,
+
+    /// Size in bytes of each ping message, padded with zeros
+    #[arg(long, default_value_t = wire::PING_HEADER_LEN, value_parser = parse_payload_size)]
+    payload_size: usize,
+
+    /// Probe each payload size in START..END [step N] over the same
+    /// connection, print the results and exit
+    #[arg(long, conflicts_with = "payload_size")]
+    sweep_payload: Option<PayloadSweep>,
+
+    /// Pings sent at each sweep step
+    #[arg(long, default_value_t = 10, requires = "sweep_payload")]
+    sweep_count: u64,
+
+    /// Print the sweep as CSV instead of a table
+    #[arg(long, requires = "sweep_payload")]
+    sweep_csv: bool,
+
+    /// Also open an unreliable, unordered channel, send every ping on both,
+    /// and compare the two at the end
+    #[arg(long, conflicts_with_all = ["unordered", "max_retransmits", "sweep_payload"])]
+    compare_reliability: bool,
+
+    /// Refuse to measure against a peer whose wire format is incompatible
+    #[arg(long)]
+    strict_seq: bool,
+
+    /// Require an echo of a probe before measuring, so one-way connectivity
+    /// fails fast (implied by --strict-seq)
+    #[arg(long)]
+    confirm_connectivity: bool,
+
+    /// Write a self-contained HTML report (summary, RTT chart, histogram)
+    /// here at the end
+    #[arg(long, value_name = "PATH")]
+    html: Option<PathBuf>,
+
+    /// Add bootstrap 95% confidence intervals on p50/p95/p99 to the summary,
+    /// from this many resamples of the RTTs (1000 is typical)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    bootstrap: Option<u64>,
+
+    /// List every ICE candidate pair at the end, with the RTT of each where
+    /// one was measured
+    #[arg(long)]
+    report_candidate_latency: bool,
+
+    /// Have the answer stamp its receive and reply times into each echo and
+    /// report the RTT split into forward and return legs (pings grow to at
+    /// least 33 bytes)
+    #[arg(
+        long,
+        conflicts_with_all = ["once", "sweep_payload", "replay", "compare_reliability"]
+    )]
+    one_way: bool,
+
+    /// Clock offset for --one-way: `wall` trusts synced system clocks,
+    /// `estimate` assumes the fastest echo was symmetric (see the oneway
+    /// module)
+    #[arg(long, default_value = "wall", requires = "one_way")]
+    clock: ClockSync,
+
+    /// Stop after sending this many pings
+    #[arg(
+        long,
+        conflicts_with_all = ["once", "sweep_payload"],
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    count: Option<u64>,
+
+    /// Stop sending pings after this many seconds
+    #[arg(
+        long,
+        value_name = "SECS",
+        conflicts_with_all = ["once", "sweep_payload"],
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    duration: Option<u64>,
+
+    /// Stop before the pings sent plus their echoes would pass this many
+    /// bytes, e.g. 100MB (suffixes B, KB, MB, GB, KiB, MiB, GiB)
+    #[arg(
+        long,
+        value_name = "SIZE",
+        conflicts_with_all = ["once", "sweep_payload"],
+        value_parser = parse_byte_size
+    )]
+    max_bytes: Option<u64>,
+
+    /// Measure a single round trip, then exit
+    #[arg(
+        long,
+        conflicts_with_all = ["sweep_payload", "replay", "rtt_target", "compare_reliability"]
+    )]
+    once: bool,
+
+    /// With --once, succeed as soon as the channel opens and one message is
+    /// sent, without waiting for an echo (for peers that never reply)
+    #[arg(long, requires = "once", conflicts_with_all = ["strict_seq", "confirm_connectivity"])]
+    connectivity_only: bool,
+
+    /// Keep a single status line updated in place instead of one line per
+    /// ping (ignored when stdout is not a terminal)
+    #[arg(long)]
+    oneline: bool,
+
+    /// Accept pause, resume and status commands on this Unix socket
+    #[arg(long)]
+    control_socket: Option<PathBuf>,
+
+    /// Periodically print stats over only the last this many seconds; the
+    /// final summary still covers the whole run
+    #[arg(
+        long,
+        alias = "max-rtt-history-seconds",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    window: Option<u64>,
+
+    /// Seconds between windowed reports
+    #[arg(long, default_value_t = 10, requires = "window")]
+    report_interval: u64,
+
+    /// Send each RTT sample as a 24-byte UDP datagram to this collector
+    /// (fire-and-forget: datagrams may be lost)
+    #[arg(long, value_name = "HOST:PORT")]
+    udp_export: Option<String>,
+
+    /// Peer id carried in exported datagrams
+    #[arg(long, default_value_t = 0, requires = "udp_export")]
+    peer_id: u64,
+
+    /// Periodically write the partial summary to this file
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Count a ping as lost once it has gone this many seconds without an
+    /// echo; a later echo for it counts as late
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    match_timeout: Option<u64>,
+
+    /// Adapt the ping rate (AIMD, see the aimd module) to hold the mean RTT
+    /// near this many milliseconds, and report the rate reached
+    #[arg(
+        long,
+        value_name = "MS",
+        conflicts_with = "sweep_payload",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    rtt_target: Option<u64>,
+
+    /// Save the time and size of every ping sent to this file, for --replay
+    #[arg(long)]
+    record_schedule: Option<PathBuf>,
+
+    /// Send pings on the schedule recorded in this file, keeping its
+    /// relative timing and sizes, then exit
+    #[arg(
+        long,
+        conflicts_with_all = ["payload_size", "sweep_payload", "rtt_target", "control_socket"]
+    )]
+    replay: Option<PathBuf>,
+
+    /// Print each run of lost sequence numbers once it is counted lost
+    #[arg(long)]
+    log_gaps: bool,
+
+    /// Run this command on each RTT breach or loss, without a shell, e.g.
+    /// 'notify-send "rtt {rtt} ms at seq {seq}"' (placeholders are listed
+    /// in the hook module)
+    #[arg(long, value_name = "CMD")]
+    on_breach: Option<String>,
+
+    /// RTT in milliseconds above which a ping counts as a breach
+    #[arg(
+        long,
+        value_name = "MS",
+        requires = "on_breach",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    breach_rtt: Option<u64>,
+
+    /// Minimum seconds between --on-breach commands; events in between are
+    /// dropped
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value_t = 10,
+        requires = "on_breach",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    breach_cooldown: u64,
+
+    /// Seconds between checkpoints
+    #[arg(long, default_value_t = 10, requires = "checkpoint")]
+    checkpoint_interval: u64,
+
+    /// Also store every RTT sample in the checkpoint
+    #[arg(long, requires = "checkpoint")]
+    checkpoint_samples: bool,
+}
+
+/// Options of the measurement channel.
+fn channel_init(args: &Args) -> RTCDataChannelInit {
+    RTCDataChannelInit {
+        ordered: Some(!args.unordered),
+        max_retransmits: args.max_retransmits,
+        protocol: args.protocol.clone(),
+        ..Default::default()
+    }
+}
+
+fn parse_payload_size(s: &str) -> Result<usize, String> {
+    let size: usize = s.parse().map_err(|e| format!("{}", e))?;
+    if size < wire::PING_HEADER_LEN {
+        return Err(format!(
+            "pings need at least {} bytes",
+            wire::PING_HEADER_LEN
+        ));
+    }
+    Ok(size)
+}
+
+fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("bad size {:?}", s))?;
+    let scale = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1u64,
+        "kb" | "k" => 1_000,
+        "mb" | "m" => 1_000_000,
+        "gb" | "g" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        other => return Err(format!("unknown size unit {:?}", other)),
+    };
+    let bytes = (number * scale as f64) as u64;
+    if bytes == 0 {
+        return Err("size must be at least one byte".to_string());
+    }
+    Ok(bytes)
+}
+
+/// Exchange format descriptors with the answer. Returns the peer's
+/// descriptor, or `None` when the peer predates the framed format and only
+/// understands legacy timestamp pings.
+async fn negotiate_format(
+    dc: &RTCDataChannel,
+    hello_rx: &mut mpsc::Receiver<FormatDescriptor>,
+    strict: bool,
+) -> Result<Option<FormatDescriptor>> {
+    dc.send(&Frame::Hello(FormatDescriptor::current()).encode())
+        .await?;
+    let peer = timeout(HELLO_TIMEOUT, hello_rx.recv()).await.ok().flatten();
+    accept_format(peer, strict)
+}
+
+/// Refuse an incompatible peer format under --strict-seq, or warn and go
+/// on best-effort without it.
+fn accept_format(peer: Option<FormatDescriptor>, strict: bool) -> Result<Option<FormatDescriptor>> {
+    if let Err(why) = wire::check_peer_format(peer.as_ref()) {
+        if strict {
+            let message = format!("{}; refusing to measure (--strict-seq)", why);
+            return Err(Failure::new(FailureKind::FormatMismatch, message).into());
+        }
+        eprintln!("warning: {}; stats may be wrong", why);
+    }
+    Ok(peer)
+}
+
+/// Send one ping and wait for its echo (--once).
+async fn ping_once(
+    dc: &RTCDataChannel,
+    stats: &Mutex<Stats>,
+    clock: Instant,
+    payload_size: usize,
+    legacy: bool,
+    echo_rx: &mut mpsc::Receiver<Sample>,
+) -> Result<Sample> {
+    let frame = if legacy {
+        Frame::Legacy {
+            sent_ns: clock.elapsed().as_nanos(),
+        }
+    } else {
+        Frame::Ping {
+            seq: 0,
+            sent_ns: clock.elapsed().as_nanos() as u64,
+            padding: payload_size - wire::PING_HEADER_LEN,
+        }
+    };
+    dc.send(&frame.encode()).await?;
+    if !legacy {
+        stats.lock().unwrap().on_sent(0, clock.elapsed());
+    }
+    match timeout(CONFIRM_TIMEOUT, echo_rx.recv()).await {
+        Ok(Some(sample)) => Ok(sample),
+        _ => bail!(Failure::new(
+            FailureKind::NoConnectivity,
+            format!("no echo of the ping within {:?}", CONFIRM_TIMEOUT)
+        )
+        .with("timeout_s", CONFIRM_TIMEOUT.as_secs())),
+    }
+}
+
+/// Send `count` pings at each payload size of the sweep and summarize the
+/// echoes of each step. Stops early, dropping the unfinished step, when the
+/// run is stopped.
+async fn run_sweep(
+    dc: &RTCDataChannel,
+    stats: &Mutex<Stats>,
+    clock: Instant,
+    sweep: PayloadSweep,
+    count: u64,
+    stop: &CancellationToken,
+) -> Result<Vec<SweepRow>> {
+    let mut rows = Vec::new();
+    let mut seq = 0;
+    for payload in sweep.sizes() {
+        let first = seq;
+        for _ in 0..count {
+            let frame = Frame::Ping {
+                seq,
+                sent_ns: clock.elapsed().as_nanos() as u64,
+                padding: payload - wire::PING_HEADER_LEN,
+            };
+            dc.send(&frame.encode()).await?;
+            stats.lock().unwrap().on_sent(seq, clock.elapsed());
+            seq += 1;
+            tokio::select! {
+                _ = sleep(SWEEP_INTERVAL) => {}
+                _ = stop.cancelled() => return Ok(rows),
+            }
+        }
+        tokio::select! {
+            _ = sleep(SWEEP_DRAIN) => {}
+            _ = stop.cancelled() => return Ok(rows),
+        }
+        let samples: Vec<Sample> = stats
+            .lock()
+            .unwrap()
+            .samples()
+            .iter()
+            .filter(|s| (first..seq).contains(&s.seq))
+            .copied()
+            .collect();
+        rows.push(SweepRow::from_samples(payload, seq - first, &samples));
+    }
+    Ok(rows)
+}
+
+fn print_sweep(rows: &[SweepRow], json: bool, csv: bool) {
+    if json {
+        for row in rows {
+            println!("{}", serde_json::json!({ "event": "sweep", "row": row }));
+        }
+    } else if csv {
+        println!("{}", sweep::CSV_HEADER);
+        for row in rows {
+            println!("{}", row.to_csv());
+        }
+    } else {
+        print!("\n=== Payload sweep ===\n{}", sweep::format_table(rows));
+    }
+}
+
+fn print_comparison(reliable: &SummaryReport, unreliable: &SummaryReport, json: bool) {
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "event": "comparison",
+                "reliable": reliable,
+                "unreliable": unreliable,
+            })
+        );
+        return;
+    }
+    println!("\n=== Reliable vs unreliable ===");
+    println!(
+        "{:<22} {:>9} {:>8} {:>10} {:>10}",
+        "channel", "recv", "loss %", "mean ms", "p95 ms"
+    );
+    for (name, r) in [
+        ("reliable, ordered", reliable),
+        ("unreliable, unordered", unreliable),
+    ] {
+        println!(
+            "{:<22} {:>9} {:>8.1} {:>10} {:>10}",
+            name,
+            format!("{}/{}", r.received, r.sent),
+            r.loss_pct,
+            ms(r.mean_ms),
+            ms(r.p95_ms)
+        );
+    }
+}
+
+/// Hand newly counted losses to --log-gaps and --on-breach.
+fn on_gaps(gaps: &[Gap], log: bool, hook: Option<&Hook>, json: bool, oneline: bool) {
+    if log {
+        print_gaps(gaps, json, oneline);
+    }
+    if let Some(hook) = hook {
+        for gap in gaps {
+            hook.fire(Event::Loss {
+                first: gap.first,
+                last: gap.last,
+            });
+        }
+    }
+}
+
+fn print_gaps(gaps: &[Gap], json: bool, oneline: bool) {
+    if oneline && !gaps.is_empty() {
+        display::clear_oneline();
+    }
+    for gap in gaps {
+        if json {
+            println!("{}", serde_json::json!({ "event": "gap", "gap": gap }));
+        } else {
+            println!("{}", gap);
+        }
+    }
+}
+
+/// Send probes until one comes back, proving data flows both ways.
+/// Returns the probe's round-trip time.
+async fn confirm_connectivity(
+    dc: &RTCDataChannel,
+    probe_rx: &mut mpsc::Receiver<()>,
+    legacy: bool,
+) -> Result<Duration> {
+    // Legacy peers echo any 16-byte timestamp; a zero one marks the probe
+    let probe = if legacy {
+        Frame::Legacy { sent_ns: 0 }
+    } else {
+        Frame::Ping {
+            seq: wire::PROBE_SEQ,
+            sent_ns: 0,
+            padding: 0,
+        }
+    }
+    .encode();
+    let start = Instant::now();
+    let deadline = start + CONFIRM_TIMEOUT;
+    loop {
+        dc.send(&probe).await?;
+        let wait = CONFIRM_RETRY.min(deadline.saturating_duration_since(Instant::now()));
+        match timeout(wait, probe_rx.recv()).await {
+            Ok(Some(())) => return Ok(start.elapsed()),
+            Ok(None) => bail!(Failure::new(
+                FailureKind::NoConnectivity,
+                "connection closed before the connectivity probe was echoed"
+            )),
+            Err(_) if Instant::now() >= deadline => bail!(Failure::new(
+                FailureKind::NoConnectivity,
+                format!(
+                    "no echo of the connectivity probe within {:?}: data is not flowing both ways",
+                    CONFIRM_TIMEOUT
+                )
+            )
+            .with("timeout_s", CONFIRM_TIMEOUT.as_secs())),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Send our wire format and wait until it has left the send buffer, since
+/// closing with it still queued would drop it.
+async fn send_hello(dc: &RTCDataChannel) -> Result<()> {
+    dc.send(&Frame::Hello(FormatDescriptor::current()).encode())
+        .await?;
+    let drained = async {
+        while dc.buffered_amount().await > 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    if timeout(CONFIRM_TIMEOUT, drained).await.is_err() {
+        bail!(Failure::new(
+            FailureKind::NoConnectivity,
+            format!(
+                "the first message was not sent within {:?}",
+                CONFIRM_TIMEOUT
+            )
+        )
+        .with("timeout_s", CONFIRM_TIMEOUT.as_secs()));
+    }
+    Ok(())
+}
+
+fn snapshot(stats: &Mutex<Stats>, clock: Instant, with_samples: bool) -> Checkpoint {
+    let stats = stats.lock().unwrap();
+    Checkpoint {
+        summary: stats.summary(clock.elapsed()),
+        samples: if with_samples {
+            stats.samples().to_vec()
+        } else {
+            Vec::new()
+        },
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
+    let args: Args = cli::parse();
+    let common = args.common.clone();
+    failure::finish(run(args).await, &common)
+}
 
-    // Build WebRTC API
-    let mut m = MediaEngine::default();
-    let api = APIBuilder::new().with_media_engine(m).build();
+async fn run(args: Args) -> Result<()> {
+    if args.common.detect_nat_type {
+        return nat::run(&args.common.stun, args.common.json).await;
+    }
+    let strict = args.strict_seq;
+    let once = args.once;
+    let connectivity_only = args.connectivity_only;
+    let confirm = args.confirm_connectivity || strict;
+    let json = args.common.json;
+    let payload_size = args.payload_size;
+    let sweep = args.sweep_payload;
+    let sweep_count = args.sweep_count;
+    let count = args.count;
+    let duration = args.duration.map(Duration::from_secs);
+    let max_bytes = args.max_bytes;
+    let one_way = args.one_way;
+    let sweep_csv = args.sweep_csv;
+    let oneline = args.oneline && display::stdout_is_tty();
+    let per_message = !oneline && !args.common.quiet;
+    let banner = args.common.show_banner();
+    let timeline = Arc::new(Timeline::default());
+    let clock = Instant::now();
+    // Offer side of the --one-way stamps: session time to wall clock
+    let wall_origin = wire::wall_ns().saturating_sub(clock.elapsed().as_nanos() as u64);
+    let ordered = !args.unordered;
+    let log_gaps = args.log_gaps;
+    let mut stats = Stats::new();
+    if ordered {
+        stats = stats.with_hol_estimate();
+    }
+    if log_gaps || args.on_breach.is_some() {
+        stats = stats.with_gap_log();
+    }
+    if let Some(secs) = args.match_timeout {
+        stats = stats.with_match_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = args.window {
+        stats = stats.with_window(Duration::from_secs(secs));
+    }
+    let stats = Arc::new(Mutex::new(stats));
+    let split = one_way.then(|| Arc::new(Mutex::new(OneWay::new(args.clock))));
+    let shutdown = CancellationToken::new();
+    let tasks = TaskTracker::new();
+    let hook = match &args.on_breach {
+        Some(template) => {
+            let cooldown = Duration::from_secs(args.breach_cooldown);
+            Some(Arc::new(Hook::new(
+                template,
+                args.breach_rtt,
+                cooldown,
+                tasks.clone(),
+            )?))
+        }
+        None => None,
+    };
+    let aimd = args.rtt_target.map(|ms| {
+        Arc::new(Mutex::new(Aimd::new(
+            Duration::from_millis(ms),
+            clock.elapsed(),
+        )))
+    });
 
-    let config = RTCConfiguration {
-        ice_servers: vec![RTCIceServer {
-            urls: vec!["stun:stun.l.google.com:19302".to_string()],
-            ..Default::default()
-        }],
-        ..Default::default()
+    let munge = args.common.munge_rules()?;
+    let replay = args.replay.as_deref().map(Schedule::load).transpose()?;
+    let replay = replay.map(Arc::new);
+    let recording = args
+        .record_schedule
+        .as_ref()
+        .map(|_| Arc::new(Mutex::new(Schedule::default())));
+    let exporter = match &args.udp_export {
+        Some(target) => Some(Arc::new(UdpExporter::connect(target, args.peer_id)?)),
+        None => None,
     };
 
+    // Build WebRTC API
+    let api = peer::build_api(&args.common)?;
+    let config = peer::rtc_config(&args.common)?;
+
     let pc = Arc::new(api.new_peer_connection(config).await?);
-    let dc = pc.create_data_channel("latency", None).await?;
+    let dc = pc
+        .create_data_channel("latency", Some(channel_init(&args)))
+        .await?;
+
+    // --compare-reliability: a second channel echoing the same pings
+    let (unreliable_open_tx, unreliable_open) = watch::channel(false);
+    let unreliable = if args.compare_reliability {
+        let init = RTCDataChannelInit {
+            ordered: Some(false),
+            max_retransmits: Some(0),
+            protocol: args.protocol.clone(),
+            ..Default::default()
+        };
+        let dc = pc.create_data_channel(UNRELIABLE_LABEL, Some(init)).await?;
+        let mut stats = Stats::new();
+        if let Some(secs) = args.match_timeout {
+            stats = stats.with_match_timeout(Duration::from_secs(secs));
+        }
+        let stats = Arc::new(Mutex::new(stats));
+        dc.on_open(Box::new(move || {
+            let _ = unreliable_open_tx.send(true);
+            Box::pin(async {})
+        }));
+        let stats2 = Arc::clone(&stats);
+        dc.on_message(Box::new(move |msg: DataChannelMessage| {
+            let now = clock.elapsed();
+            if let Some(Frame::Ping { seq, sent_ns, .. }) = Frame::decode(&msg.data) {
+                if seq != wire::PROBE_SEQ {
+                    let sent = Duration::from_nanos(sent_ns);
+                    stats2.lock().unwrap().on_echo(seq, sent, now);
+                }
+            }
+            Box::pin(async {})
+        }));
+        Some((dc, stats))
+    } else {
+        None
+    };
+    let keepalive = Keepalive::from_args(&args.common);
+    let keepalive2 = keepalive.clone();
+    let unreliable2 = unreliable.clone();
+    let mut unreliable_open2 = unreliable_open.clone();
     let dc2 = Arc::clone(&dc);
+    let pc2 = Arc::clone(&pc);
+    let timeline2 = Arc::clone(&timeline);
+    let stats2 = Arc::clone(&stats);
+    let aimd2 = aimd.clone();
+    let replay2 = replay.clone();
+    let recording2 = recording.clone();
+    let (hello_tx, mut hello_rx) = mpsc::channel(1);
+    let (probe_tx, mut probe_rx) = mpsc::channel(1);
+    let (echo_tx, mut echo_rx) = mpsc::channel(1);
+    let (fail_tx, mut fail_rx) = mpsc::channel(1);
+    let (done_tx, mut done_rx) = mpsc::channel::<()>(1);
+    let error_tx = fail_tx.clone();
+    let deadline_tx = fail_tx.clone();
+    let send_stop = shutdown.clone();
+    let tasks2 = tasks.clone();
+    let (pause_tx, pause_rx) = watch::channel(false);
+    let mut paused = pause_rx.clone();
 
-    // When DataChannel opens: start sending pings
+    // When DataChannel opens: agree on the wire format, then start sending pings
     dc.on_open(Box::new(move || {
         let dc3 = Arc::clone(&dc2);
-        Box::pin(async move {
-            println!("DataChannel open, sending pings...");
-            loop {
-                let now = Instant::now().elapsed().as_nanos().to_le_bytes();
-                if let Err(e) = dc3.send(&Bytes::from(now.to_vec())).await {
+        let tasks = tasks2.clone();
+        // Tracked, so shutdown waits for the ping loop to stop
+        tasks2.spawn(async move {
+            timeline2.mark("channel open", clock.elapsed());
+            println!("DataChannel open (protocol: {:?})", dc3.protocol());
+            path::print_selected_pair(&pc2, json).await;
+            if banner {
+                Banner::collect("offer", &pc2, &dc3, &timeline2)
+                    .await
+                    .print(json);
+            }
+            // A sink that never replies would also never send its format
+            if connectivity_only {
+                match send_hello(&dc3).await {
+                    Ok(()) => {
+                        println!("Connectivity confirmed: channel open and a message sent");
+                        let _ = done_tx.send(()).await;
+                    }
+                    Err(e) => {
+                        let _ = fail_tx.send(e).await;
+                    }
+                }
+                return;
+            }
+            let peer_format = match negotiate_format(&dc3, &mut hello_rx, strict).await {
+                Ok(peer_format) => peer_format,
+                Err(e) => {
+                    let _ = fail_tx.send(e).await;
+                    return;
+                }
+            };
+            let legacy = peer_format.is_none();
+            if legacy {
+                eprintln!("warning: falling back to legacy timestamp pings");
+            }
+            let stamped = one_way && peer_format.is_some_and(|p| p.has(wire::FEATURE_STAMP));
+            if one_way && !stamped {
+                eprintln!("warning: the answer cannot stamp echoes; no --one-way split");
+            }
+            if let Some(keepalive) = keepalive2 {
+                if peer_format.is_some_and(|p| p.has(wire::FEATURE_KEEPALIVE)) {
+                    let dc = Arc::clone(&dc3);
+                    tasks.spawn(keepalive.run(dc, fail_tx.clone(), send_stop.clone()));
+                } else {
+                    eprintln!("warning: the answer does not answer keepalives; no --keepalive");
+                }
+            }
+
+            // A ping larger than the channel can carry would fail every send
+            let max_message_size = peer::max_message_size(&pc2).await;
+            let largest = match (&sweep, &replay2) {
+                (Some(sweep), _) => sweep.largest(),
+                (None, Some(replay)) => replay.largest(),
+                (None, None) => payload_size,
+            };
+            if !legacy {
+                if let Err(e) = peer::check_message_size(largest, max_message_size) {
+                    let _ = fail_tx.send(e).await;
+                    return;
+                }
+            }
+
+            if confirm {
+                match confirm_connectivity(&dc3, &mut probe_rx, legacy).await {
+                    Ok(rtt) => {
+                        timeline2.mark("connectivity confirmed", clock.elapsed());
+                        println!(
+                            "Connectivity confirmed ({:.2} ms)",
+                            rtt.as_secs_f64() * 1000.0
+                        );
+                    }
+                    Err(e) => {
+                        let _ = fail_tx.send(e).await;
+                        return;
+                    }
+                }
+            }
+
+            if once {
+                match ping_once(&dc3, &stats2, clock, payload_size, legacy, &mut echo_rx).await {
+                    Ok(_) => {
+                        let _ = done_tx.send(()).await;
+                    }
+                    Err(e) => {
+                        let _ = fail_tx.send(e).await;
+                    }
+                }
+                return;
+            }
+
+            if let Some(sweep) = sweep {
+                if legacy {
+                    let _ = fail_tx
+                        .send(anyhow::anyhow!(
+                            "--sweep-payload needs a peer that echoes framed pings"
+                        ))
+                        .await;
+                    return;
+                }
+                println!("Sweeping payload sizes {}...", sweep);
+                match run_sweep(&dc3, &stats2, clock, sweep, sweep_count, &send_stop).await {
+                    Ok(rows) => {
+                        print_sweep(&rows, json, sweep_csv);
+                        let _ = done_tx.send(()).await;
+                    }
+                    Err(e) => {
+                        let _ = fail_tx.send(e).await;
+                    }
+                }
+                return;
+            }
+            if legacy && replay2.is_some() {
+                let _ = fail_tx
+                    .send(anyhow::anyhow!(
+                        "--replay needs a peer that echoes framed pings"
+                    ))
+                    .await;
+                return;
+            }
+            if let Some((dc, _)) = &unreliable2 {
+                if legacy {
+                    let _ = fail_tx
+                        .send(anyhow::anyhow!(
+                            "--compare-reliability needs a peer that echoes framed pings"
+                        ))
+                        .await;
+                    return;
+                }
+                let opened = unreliable_open2.wait_for(|open| *open);
+                if timeout(HELLO_TIMEOUT, opened).await.is_err() {
+                    let _ = fail_tx
+                        .send(anyhow::anyhow!(
+                            "the {} channel did not open within {:?}",
+                            UNRELIABLE_LABEL,
+                            HELLO_TIMEOUT
+                        ))
+                        .await;
+                    return;
+                }
+                // The answer checks the format on every channel it echoes on
+                let hello = Frame::Hello(FormatDescriptor::current()).encode();
+                if let Err(e) = dc.send(&hello).await {
+                    eprintln!("{} send error: {:?}", UNRELIABLE_LABEL, e);
+                }
+                println!("Comparing with an unreliable, unordered channel");
+            }
+            println!("Sending pings...");
+            if oneline {
+                let stats = Arc::clone(&stats2);
+                let stop = send_stop.clone();
+                tasks.spawn(async move {
+                    while !stop.is_cancelled() {
+                        let report = stats.lock().unwrap().summary(clock.elapsed());
+                        display::redraw_oneline(&report);
+                        tokio::select! {
+                            _ = sleep(display::ONELINE_REFRESH) => {}
+                            _ = stop.cancelled() => {}
+                        }
+                    }
+                });
+            }
+            if let Some(aimd) = &aimd2 {
+                aimd.lock().unwrap().restart(clock.elapsed());
+            }
+            let replay_start = tokio::time::Instant::now();
+            let mut seq = 0;
+            // Bytes of the pings sent and of the echoes they bring back
+            let mut transferred = 0u64;
+            let channels = if unreliable2.is_some() { 2 } else { 1 };
+            while !send_stop.is_cancelled() {
+                if *paused.borrow_and_update() {
+                    stats2.lock().unwrap().on_pause(clock.elapsed());
+                    if oneline {
+                        display::clear_oneline();
+                    }
+                    println!("Pings paused");
+                    while *paused.borrow_and_update() && !send_stop.is_cancelled() {
+                        tokio::select! {
+                            _ = paused.changed() => {}
+                            _ = send_stop.cancelled() => {}
+                        }
+                    }
+                    stats2.lock().unwrap().on_resume(clock.elapsed());
+                    if let Some(aimd) = &aimd2 {
+                        aimd.lock().unwrap().restart(clock.elapsed());
+                    }
+                    if !send_stop.is_cancelled() {
+                        println!("Pings resumed");
+                    }
+                    continue;
+                }
+                let size = match &replay2 {
+                    Some(replay) => match replay.sends.get(seq as usize) {
+                        Some(send) => send.size,
+                        None => {
+                            tokio::select! {
+                                _ = sleep(DRAIN) => {}
+                                _ = send_stop.cancelled() => {}
+                            }
+                            let _ = done_tx.send(()).await;
+                            break;
+                        }
+                    },
+                    None => payload_size,
+                };
+                let frame = if legacy {
+                    Frame::Legacy {
+                        sent_ns: clock.elapsed().as_nanos(),
+                    }
+                } else if stamped {
+                    Frame::Stamped {
+                        seq,
+                        sent_ns: clock.elapsed().as_nanos() as u64,
+                        recv_ns: 0,
+                        reply_ns: 0,
+                        padding: size.saturating_sub(wire::STAMPED_HEADER_LEN),
+                    }
+                } else {
+                    Frame::Ping {
+                        seq,
+                        sent_ns: clock.elapsed().as_nanos() as u64,
+                        padding: size - wire::PING_HEADER_LEN,
+                    }
+                };
+                let bytes = frame.encode();
+
+                // Bounded runs stop before the ping that would pass a limit
+                let cost = 2 * channels * bytes.len() as u64;
+                let limit = if count.is_some_and(|n| seq >= n) {
+                    Some("--count")
+                } else if duration.is_some_and(|d| replay_start.elapsed() >= d) {
+                    Some("--duration")
+                } else if max_bytes.is_some_and(|cap| transferred + cost > cap) {
+                    Some("--max-bytes")
+                } else {
+                    None
+                };
+                if let Some(limit) = limit {
+                    if oneline {
+                        display::clear_oneline();
+                    }
+                    println!(
+                        "Reached {}: {} pings, {} bytes sent and echoed",
+                        limit, seq, transferred
+                    );
+                    tokio::select! {
+                        _ = sleep(DRAIN) => {}
+                        _ = send_stop.cancelled() => {}
+                    }
+                    let _ = done_tx.send(()).await;
+                    break;
+                }
+                transferred += cost;
+
+                if let Err(e) = dc3.send(&bytes).await {
                     eprintln!("send error: {:?}", e);
                     break;
                 }
-                sleep(Duration::from_secs(1)).await;
+                if !legacy {
+                    stats2.lock().unwrap().on_sent(seq, clock.elapsed());
+                }
+                if let Some(recording) = &recording2 {
+                    recording
+                        .lock()
+                        .unwrap()
+                        .record(clock.elapsed(), bytes.len());
+                }
+                if let Some((dc, stats)) = &unreliable2 {
+                    match dc.send(&bytes).await {
+                        Ok(_) => stats.lock().unwrap().on_sent(seq, clock.elapsed()),
+                        Err(e) => eprintln!("{} send error: {:?}", UNRELIABLE_LABEL, e),
+                    }
+                }
+                seq += 1;
+                let interval = match (&replay2, &aimd2) {
+                    // Pace from the start of the replay so delays do not add up
+                    (Some(replay), _) => {
+                        replay
+                            .sends
+                            .get(seq as usize)
+                            .map_or(Duration::ZERO, |next| {
+                                (replay_start + next.at())
+                                    .saturating_duration_since(tokio::time::Instant::now())
+                            })
+                    }
+                    (None, Some(aimd)) => {
+                        let mut aimd = aimd.lock().unwrap();
+                        aimd.tick(clock.elapsed());
+                        aimd.interval()
+                    }
+                    (None, None) => Duration::from_secs(1),
+                };
+                tokio::select! {
+                    _ = sleep(interval) => {}
+                    _ = send_stop.cancelled() => {}
+                    Ok(()) = paused.changed() => {}
+                }
             }
-        })
+        });
+        Box::pin(async {})
     }));
 
     // On message: measure latency
+    let stats2 = Arc::clone(&stats);
+    let aimd2 = aimd.clone();
+    let split2 = split.clone();
+    let hook2 = hook.clone();
+    let dc_pong = Arc::clone(&dc);
     dc.on_message(Box::new(move |msg: DataChannelMessage| {
+        let hello_tx = hello_tx.clone();
+        let probe_tx = probe_tx.clone();
+        let echo_tx = echo_tx.clone();
+        let stats = Arc::clone(&stats2);
+        let exporter = exporter.clone();
+        let aimd = aimd2.clone();
+        let split = split2.clone();
+        let hook = hook2.clone();
+        let keepalive = keepalive.clone();
+        let dc_pong = Arc::clone(&dc_pong);
         Box::pin(async move {
-            if msg.data.len() == 16 {
-                let sent_ns = u128::from_le_bytes(msg.data[..16].try_into().unwrap());
-                let now = Instant::now().elapsed().as_nanos();
-                let rtt = now.saturating_sub(sent_ns);
-                println!("RTT: {:.2} ms", rtt as f64 / 1_000_000.0);
-            } else {
-                println!("Received: {}", String::from_utf8_lossy(&msg.data));
+            let now = clock.elapsed();
+            let sample = match Frame::decode(&msg.data) {
+                Some(Frame::Hello(peer)) => {
+                    let _ = hello_tx.send(peer).await;
+                    None
+                }
+                Some(Frame::Ping {
+                    seq: wire::PROBE_SEQ,
+                    ..
+                })
+                | Some(Frame::Legacy { sent_ns: 0 }) => {
+                    let _ = probe_tx.try_send(());
+                    None
+                }
+                Some(Frame::Ping { seq, sent_ns, .. }) => {
+                    let sent = Duration::from_nanos(sent_ns);
+                    let mut stats = stats.lock().unwrap();
+                    let sample = stats.on_echo(seq, sent, now);
+                    on_gaps(&stats.take_gaps(), log_gaps, hook.as_deref(), json, oneline);
+                    sample
+                }
+                Some(Frame::Stamped {
+                    seq,
+                    sent_ns,
+                    recv_ns,
+                    reply_ns,
+                    ..
+                }) => {
+                    let sent = Duration::from_nanos(sent_ns);
+                    let mut stats = stats.lock().unwrap();
+                    let sample = stats.on_echo(seq, sent, now);
+                    on_gaps(&stats.take_gaps(), log_gaps, hook.as_deref(), json, oneline);
+                    if let (Some(_), Some(split)) = (sample, &split) {
+                        split.lock().unwrap().record(Stamp {
+                            t0: wall_origin + sent_ns,
+                            t1: recv_ns,
+                            t2: reply_ns,
+                            t3: wall_origin + now.as_nanos() as u64,
+                        });
+                    }
+                    sample
+                }
+                Some(Frame::Legacy { sent_ns }) => {
+                    let rtt = now.saturating_sub(Duration::from_nanos(sent_ns as u64));
+                    Some(stats.lock().unwrap().on_legacy_echo(rtt, now))
+                }
+                Some(Frame::KeepalivePing { nonce }) => {
+                    let pong = Frame::KeepalivePong { nonce }.encode();
+                    if let Err(e) = dc_pong.send(&pong).await {
+                        eprintln!("keepalive send error: {:?}", e);
+                    }
+                    None
+                }
+                Some(Frame::KeepalivePong { .. }) => {
+                    if let Some(keepalive) = &keepalive {
+                        keepalive.on_pong();
+                    }
+                    None
+                }
+                None => {
+                    if per_message {
+                        println!("Received: {}", String::from_utf8_lossy(&msg.data));
+                    }
+                    None
+                }
+            };
+            if let (Some(sample), true) = (sample, once) {
+                let _ = echo_tx.try_send(sample);
+            }
+            if let (Some(sample), Some(exporter)) = (sample, &exporter) {
+                exporter.send(&sample);
+            }
+            if let (Some(sample), Some(aimd)) = (sample, &aimd) {
+                let rtt = Duration::from_secs_f64(sample.rtt_ms / 1000.0);
+                aimd.lock().unwrap().on_rtt(rtt);
+            }
+            if let (Some(sample), Some(hook)) = (sample, &hook) {
+                hook.on_rtt(sample.seq, sample.rtt_ms);
+            }
+            if let (Some(sample), true) = (sample, per_message) {
+                println!("seq={} RTT: {:.2} ms", sample.seq, sample.rtt_ms);
             }
         })
     }));
 
+    dc.on_error(peer::on_channel_error(error_tx));
+
+    let timeline2 = Arc::clone(&timeline);
+    pc.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+        if state == RTCPeerConnectionState::Connected {
+            timeline2.mark("connected", clock.elapsed());
+        }
+        Box::pin(async {})
+    }));
+
+    // Bind before printing the offer so a bad path fails early
+    if let Some(path) = &args.control_socket {
+        let listener = control::bind(path)?;
+        tasks.spawn(control::serve(listener, pause_tx.clone(), shutdown.clone()));
+    }
+
     // === Create and show offer SDP ===
-    let offer = pc.create_offer(None).await?;
-    pc.set_local_description(offer.clone()).await?;
+    let trickle_only = args.common.no_trickle_in_sdp;
+    let candidates = (trickle_only || args.common.print_candidates).then(|| trickle::collect(&pc));
+    let offer = peer::create_offer(&pc).await?;
+    let mut gather_complete = pc.gathering_complete_promise().await;
+    pc.set_local_description(offer).await?;
 
-    let sdp = serde_json::to_string(&offer)?;
+    // Wait for ICE gathering so the offer carries our candidates
+    if !trickle_only {
+        let _ = gather_complete.recv().await;
+    }
+    let mut offer = pc
+        .local_description()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("missing local description"))?;
+    if trickle_only {
+        trickle::strip_candidates(&mut offer)?;
+    }
+    if let Some(rules) = &munge {
+        rules.apply(&mut offer)?;
+    }
+    peer::check_gathered(&offer, &args.common)?;
+    if args.common.canonicalize_sdp {
+        signal::canonicalize(&mut offer)?;
+    }
+    timeline.mark("offer ready", clock.elapsed());
     println!("\n=== Copy this OFFER and send to the other peer ===\n");
-    println!("{}", base64::encode(sdp));
+    println!("{}", signal::encode_sdp(&offer)?);
+    if let Some(rx) = candidates {
+        let mid = sdp::first_mid(&offer.sdp);
+        tasks.spawn(trickle::print_local(
+            rx,
+            mid,
+            trickle_only,
+            shutdown.clone(),
+        ));
+    }
 
     // === Read answer SDP from stdin ===
     println!("\n=== Paste the ANSWER from the other peer and press Enter ===");
     let mut line = String::new();
     io::stdin().read_line(&mut line)?;
-    let answer_json = String::from_utf8(base64::decode(line.trim())?)?;
-    let answer = serde_json::from_str(&answer_json)?;
+    let mut answer = signal::decode_sdp(&line)?;
+    if answer.sdp_type == RTCSdpType::Offer {
+        return Err(Failure::new(
+            FailureKind::BothOfferers,
+            "the pasted blob is an offer: both peers ran `offer`; run `answer` on one side",
+        )
+        .into());
+    }
+    let remote_trickles = sdp::candidates(&answer.sdp).is_empty();
+    peer::filter_remote_candidates(&mut answer, &args.common.address_filter());
+    peer::check_ip_versions(&offer.sdp, &answer.sdp)?;
     pc.set_remote_description(answer).await?;
+    timeline.mark("answer applied", clock.elapsed());
+    if let Some(secs) = args.common.connect_timeout {
+        tasks.spawn(peer::connect_deadline(
+            Arc::downgrade(&pc),
+            Duration::from_secs(secs),
+            deadline_tx,
+            shutdown.clone(),
+        ));
+    }
+    if remote_trickles {
+        println!("\n=== Paste the CANDIDATES from the other peer, one per line ===");
+        tasks.spawn(trickle::add_remote(
+            Arc::downgrade(&pc),
+            args.common.address_filter(),
+            signal::stdin_lines(),
+            shutdown.clone(),
+        ));
+    }
+    if args.common.warn_on_srflx_change {
+        tasks.spawn(srflx::watch(
+            Arc::downgrade(&pc),
+            peer::stun_urls(&args.common),
+            json,
+            shutdown.clone(),
+        ));
+    }
 
-    // Wait forever
-    tokio::signal::ctrl_c().await?;
-    Ok(())
+    // Report recent behavior while the run goes on
+    if let Some(window) = args.window {
+        let stats = Arc::clone(&stats);
+        let interval = Duration::from_secs(args.report_interval);
+        let stop = shutdown.clone();
+        tasks.spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = sleep(interval) => {}
+                    _ = stop.cancelled() => break,
+                }
+                let Some(report) = stats.lock().unwrap().window_summary(clock.elapsed()) else {
+                    break;
+                };
+                if json {
+                    println!(
+                        "{}",
+                        serde_json::json!({
+                            "event": "window",
+                            "window_s": window,
+                            "summary": report,
+                        })
+                    );
+                } else {
+                    if oneline {
+                        display::clear_oneline();
+                    }
+                    println!("\n=== Last {}s ===\n{}", window, report);
+                }
+            }
+        });
+    }
+
+    // Persist partial results while the run goes on
+    if let Some(path) = args.checkpoint.clone() {
+        let stats = Arc::clone(&stats);
+        let interval = Duration::from_secs(args.checkpoint_interval);
+        let with_samples = args.checkpoint_samples;
+        let stop = shutdown.clone();
+        tasks.spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = sleep(interval) => {}
+                    _ = stop.cancelled() => break,
+                }
+                let checkpoint = snapshot(&stats, clock, with_samples);
+                if let Err(e) = checkpoint.write_atomic(&path) {
+                    eprintln!("checkpoint error: {:#}", e);
+                }
+            }
+        });
+    }
+
+    // Run until interrupted, a bounded mode finishes, or the format check fails
+    let mut bounded = false;
+    let outcome = tokio::select! {
+        res = tokio::signal::ctrl_c() => res.map_err(anyhow::Error::from),
+        Some(()) = done_rx.recv() => {
+            bounded = true;
+            Ok(())
+        }
+        Some(e) = fail_rx.recv() => Err(e),
+    };
+
+    // Shut down in a fixed order: stop every task, finalize stats, write
+    // every output, and only then close the connection
+    shutdown::stop(&shutdown, &tasks).await;
+    // A bounded run has waited out its last echoes, so what is missing is lost
+    if bounded {
+        stats.lock().unwrap().finish();
+        if let Some((_, stats)) = &unreliable {
+            stats.lock().unwrap().finish();
+        }
+    }
+    let mut report = {
+        let mut stats = stats.lock().unwrap();
+        if log_gaps {
+            print_gaps(&stats.finish_gaps(), json, oneline);
+        }
+        let mut report = stats.summary(clock.elapsed());
+        if let Some(resamples) = args.bootstrap {
+            let rtts: Vec<f64> = stats.samples().iter().map(|s| s.rtt_ms).collect();
+            report.bootstrap = ci::bootstrap(&rtts, resamples);
+        }
+        report
+    };
+    if let Some(aimd) = &aimd {
+        report.rtt_target = Some(aimd.lock().unwrap().report(payload_size));
+    }
+    if let Some(split) = &split {
+        report.one_way = split.lock().unwrap().report();
+    }
+    if let Some(path) = &args.checkpoint {
+        if let Err(e) = snapshot(&stats, clock, args.checkpoint_samples).write_atomic(path) {
+            eprintln!("checkpoint error: {:#}", e);
+        }
+    }
+    if oneline {
+        println!();
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({ "event": "summary", "summary": report })
+        );
+    } else {
+        println!("\n=== Summary ===\n{}", report);
+    }
+    if let Some((_, stats)) = &unreliable {
+        let other = stats.lock().unwrap().summary(clock.elapsed());
+        print_comparison(&report, &other, json);
+    }
+    if let Some(path) = &args.html {
+        let title = match path::selected_pair(&pc).await {
+            Some(pair) => format!("WebRTC latency: {}", pair),
+            None => "WebRTC latency".to_string(),
+        };
+        let written = html::write(path, &title, &report, stats.lock().unwrap().samples());
+        match written {
+            Ok(()) => println!("Wrote report to {}", path.display()),
+            Err(e) => eprintln!("html error: {:#}", e),
+        }
+    }
+    if let Some(hook) = hook.as_ref().filter(|h| h.suppressed() > 0) {
+        println!(
+            "--on-breach: {} events dropped by the rate limit",
+            hook.suppressed()
+        );
+    }
+    if args.report_candidate_latency {
+        path::print_candidate_pairs(&path::candidate_pairs(&pc, report.mean_ms).await, json);
+    }
+    if let (Some(path), Some(recording)) = (&args.record_schedule, &recording) {
+        let recording = recording.lock().unwrap();
+        match recording.save(path) {
+            Ok(()) => println!(
+                "Recorded {} sends to {}",
+                recording.sends.len(),
+                path.display()
+            ),
+            Err(e) => eprintln!("schedule error: {:#}", e),
+        }
+    }
+    io::stdout().flush()?;
+    if let Some(path) = &args.control_socket {
+        let _ = std::fs::remove_file(path);
+    }
+
+    peer::close_quietly(pc).await;
+    outcome
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("offer").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn byte_sizes_take_decimal_and_binary_suffixes() {
+        for (text, bytes) in [
+            ("1500", 1500),
+            ("1500b", 1500),
+            ("2k", 2_000),
+            ("2KB", 2_000),
+            ("1.5mb", 1_500_000),
+            ("3 M", 3_000_000),
+            ("1g", 1_000_000_000),
+            ("1KiB", 1024),
+            ("2mib", 2 << 20),
+            ("1gib", 1 << 30),
+            (" 0.5kib ", 512),
+        ] {
+            assert_eq!(parse_byte_size(text), Ok(bytes), "{:?}", text);
+        }
+        for text in ["", "k", "12x", "1.2.3k", "0", "0.0001k", "-1k"] {
+            assert!(parse_byte_size(text).is_err(), "{:?}", text);
+        }
+        assert_eq!(parse(&["--max-bytes", "10MiB"]).max_bytes, Some(10 << 20));
+    }
+
+    #[test]
+    fn strict_seq_refuses_incompatible_formats() {
+        let ours = FormatDescriptor::current();
+        let incompatible = [
+            Some(FormatDescriptor {
+                version: ours.version + 1,
+                ..ours
+            }),
+            Some(FormatDescriptor {
+                features: ours.features & !wire::FEATURE_SEQ,
+                ..ours
+            }),
+            None,
+        ];
+        for peer in incompatible {
+            let err = accept_format(peer, true).unwrap_err();
+            assert_eq!(
+                failure::to_json(&err)["kind"],
+                "format_mismatch",
+                "{:?}",
+                peer
+            );
+            assert!(err.to_string().contains("--strict-seq"));
+            // Without --strict-seq the run goes on best-effort
+            assert_eq!(accept_format(peer, false).unwrap(), peer);
+        }
+        // Optional features may be missing
+        let older = FormatDescriptor {
+            features: wire::FEATURE_SEQ | wire::FEATURE_TYPE_TAG,
+            ..ours
+        };
+        assert_eq!(accept_format(Some(older), true).unwrap(), Some(older));
+    }
+
+    #[test]
+    fn channel_init_carries_the_options() {
+        let init = channel_init(&parse(&["--protocol", "myproto"]));
+        assert_eq!(init.protocol.as_deref(), Some("myproto"));
+        assert_eq!(init.ordered, Some(true));
+        let init = channel_init(&parse(&["--unordered", "--max-retransmits", "2"]));
+        assert_eq!(init.protocol, None);
+        assert_eq!(init.ordered, Some(false));
+        assert_eq!(init.max_retransmits, Some(2));
+    }
+
+    /// Split a line rendered by `cli::effective_args` back into words:
+    /// plain words, or single-quoted ones with `'\''` for a quote.
+    fn shell_words(line: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut word = None::<String>;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' => words.extend(word.take()),
+                '\'' => {
+                    let word = word.get_or_insert_with(String::new);
+                    word.extend(chars.by_ref().take_while(|&c| c != '\''));
+                }
+                '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+                c => word.get_or_insert_with(String::new).push(c),
+            }
+        }
+        words.extend(word);
+        words
+    }
+
+    #[test]
+    fn effective_args_parse_back_to_the_same_args() {
+        let argv = [
+            "offer",
+            "--stun",
+            "stun:a.example:3478,stun:b.example:3478",
+            "--deny-cidr",
+            "10.0.0.0/8",
+            "--deny-cidr",
+            "fd00::/8",
+            "--port-min",
+            "41000",
+            "--port-max",
+            "41099",
+            "--payload-size",
+            "512",
+            "--unordered",
+            "--protocol",
+            "my proto",
+            "--on-breach",
+            "notify-send 'rtt {rtt} ms' \"it's slow\"",
+            "--breach-rtt",
+            "150",
+            "--print-effective-args",
+        ];
+        let matches = Args::command().try_get_matches_from(argv).unwrap();
+        let args = Args::from_arg_matches(&matches).unwrap();
+        let line = cli::effective_args(&Args::command(), &args, &matches).unwrap();
+        let words = shell_words(&line);
+        assert!(
+            !words.iter().any(|w| w == "--print-effective-args"),
+            "{}",
+            line
+        );
+        // Defaults stay implicit
+        assert!(!words.iter().any(|w| w == "--report-interval"), "{}", line);
+
+        let reparsed = Args::try_parse_from(&words).unwrap();
+        let mut expected = serde_json::to_value(&args).unwrap();
+        expected["common"]["print_effective_args"] = false.into();
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), expected);
+        // Rendering is canonical, so a second round changes nothing
+        let matches = Args::command().try_get_matches_from(&words).unwrap();
+        assert_eq!(
+            cli::effective_args(&Args::command(), &reparsed, &matches).unwrap(),
+            line
+        );
+    }
+}
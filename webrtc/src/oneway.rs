@@ -0,0 +1,229 @@
+//! Splitting the RTT into its forward and return legs for --one-way.
+//!
+//! The answer stamps each echo with the time it received the ping (t1) and
+//! the time it replied (t2). With the offer's send and receive times t0 and
+//! t3, and `offset` the answer's clock minus the offer's:
+//!
+//! - forward = t1 - t0 - offset
+//! - return = t3 - t2 + offset
+//!
+//! The two legs always add up to the RTT less the answer's processing time.
+//! Everything hinges on the offset:
+//!
+//! - `wall` trusts the system clocks, offset 0. Right when both hosts are
+//!   synced (NTP, PTP); any sync error moves straight from one leg to the
+//!   other.
+//! - `estimate` uses NTP's two-way estimate on the echo with the least
+//!   network time, assuming its legs were equal. It needs no clock sync but
+//!   cannot see a constant asymmetry, only how the legs vary around it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::stats::percentile;
+
+/// Where the clock offset between the peers comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockSync {
+    Wall,
+    Estimate,
+}
+
+impl FromStr for ClockSync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wall" => Ok(ClockSync::Wall),
+            "estimate" => Ok(ClockSync::Estimate),
+            _ => Err(format!("expected wall or estimate, got {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ClockSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ClockSync::Wall => "wall",
+            ClockSync::Estimate => "estimate",
+        })
+    }
+}
+
+/// The four timestamps of one stamped echo, in wall-clock ns.
+#[derive(Debug, Clone, Copy)]
+pub struct Stamp {
+    /// Offer sent the ping
+    pub t0: u64,
+    /// Answer received it
+    pub t1: u64,
+    /// Answer replied
+    pub t2: u64,
+    /// Offer received the echo
+    pub t3: u64,
+}
+
+impl Stamp {
+    /// Forward and return legs in ms for a clock offset in ns.
+    fn legs(&self, offset: i128) -> (f64, f64) {
+        let forward = self.t1 as i128 - self.t0 as i128 - offset;
+        let back = self.t3 as i128 - self.t2 as i128 + offset;
+        (forward as f64 / 1e6, back as f64 / 1e6)
+    }
+
+    /// Time spent on the network, excluding the answer's processing.
+    fn network(&self) -> i128 {
+        (self.t3 as i128 - self.t0 as i128) - (self.t2 as i128 - self.t1 as i128)
+    }
+
+    /// NTP's offset estimate, exact when both legs took equally long.
+    fn offset(&self) -> i128 {
+        ((self.t1 as i128 - self.t0 as i128) + (self.t2 as i128 - self.t3 as i128)) / 2
+    }
+}
+
+/// Stamped echoes collected over the run.
+#[derive(Debug)]
+pub struct OneWay {
+    clock: ClockSync,
+    stamps: Vec<Stamp>,
+}
+
+impl OneWay {
+    pub fn new(clock: ClockSync) -> Self {
+        OneWay {
+            clock,
+            stamps: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, stamp: Stamp) {
+        self.stamps.push(stamp);
+    }
+
+    /// The split over every echo so far, or `None` before the first.
+    pub fn report(&self) -> Option<OneWayReport> {
+        let offset = match self.clock {
+            ClockSync::Wall => 0,
+            ClockSync::Estimate => self.stamps.iter().min_by_key(|s| s.network())?.offset(),
+        };
+        let (mut forward, mut back): (Vec<f64>, Vec<f64>) =
+            self.stamps.iter().map(|s| s.legs(offset)).unzip();
+        if forward.is_empty() {
+            return None;
+        }
+        forward.sort_by(f64::total_cmp);
+        back.sort_by(f64::total_cmp);
+        let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+        Some(OneWayReport {
+            clock: self.clock,
+            offset_ms: offset as f64 / 1e6,
+            forward_ms: mean(&forward),
+            return_ms: mean(&back),
+            forward_p50_ms: percentile(&forward, 50.0),
+            return_p50_ms: percentile(&back, 50.0),
+            samples: forward.len() as u64,
+        })
+    }
+}
+
+/// The forward/return split of the RTT.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OneWayReport {
+    pub clock: ClockSync,
+    /// Answer clock minus offer clock, as assumed or estimated
+    pub offset_ms: f64,
+    /// Mean offer-to-answer delay
+    pub forward_ms: f64,
+    /// Mean answer-to-offer delay
+    pub return_ms: f64,
+    pub forward_p50_ms: Option<f64>,
+    pub return_p50_ms: Option<f64>,
+    pub samples: u64,
+}
+
+impl fmt::Display for OneWayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "one-way forward/return = {:.2}/{:.2} ms mean over {} echoes \
+             (clock {}, offset {:.2} ms)",
+            self.forward_ms, self.return_ms, self.samples, self.clock, self.offset_ms
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    /// A ping a second taking `forward` ms out and 2 ms back, with 1 ms at
+    /// the answer, whose clock reads `skew` ms ahead of the offer's.
+    fn stamp(i: u64, forward: u64, skew: u64) -> Stamp {
+        let t0 = (i + 1) * 1000 * MS;
+        let t1 = t0 + (forward + skew) * MS;
+        let t2 = t1 + MS;
+        Stamp {
+            t0,
+            t1,
+            t2,
+            t3: t2 - skew * MS + 2 * MS,
+        }
+    }
+
+    fn report(clock: ClockSync, stamps: impl IntoIterator<Item = Stamp>) -> OneWayReport {
+        let mut oneway = OneWay::new(clock);
+        for s in stamps {
+            oneway.record(s);
+        }
+        oneway.report().unwrap()
+    }
+
+    fn assert_ms(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn synced_wall_clocks_give_the_true_split() {
+        let r = report(ClockSync::Wall, (0..5).map(|i| stamp(i, 10, 0)));
+        assert_ms(r.forward_ms, 10.0);
+        assert_ms(r.return_ms, 2.0);
+        assert_eq!(r.forward_p50_ms, Some(10.0));
+        assert_eq!(r.return_p50_ms, Some(2.0));
+        assert_ms(r.offset_ms, 0.0);
+        assert_eq!(r.samples, 5);
+        assert!(OneWay::new(ClockSync::Wall).report().is_none());
+    }
+
+    #[test]
+    fn wall_clock_skew_moves_between_the_legs() {
+        let r = report(ClockSync::Wall, (0..5).map(|i| stamp(i, 10, 5)));
+        assert_ms(r.forward_ms, 15.0);
+        assert_ms(r.return_ms, -3.0);
+    }
+
+    #[test]
+    fn the_estimate_splits_evenly_whatever_the_skew() {
+        for skew in [0, 5] {
+            // One ping held up 4 ms more on the way out
+            let stamps = (0..5).map(|i| stamp(i, if i == 2 { 14 } else { 10 }, skew));
+            let r = report(ClockSync::Estimate, stamps);
+            // A constant asymmetry is invisible: 12 ms split down the middle
+            assert_ms(r.offset_ms, 4.0 + skew as f64);
+            assert_eq!(r.forward_p50_ms, Some(6.0));
+            assert_eq!(r.return_p50_ms, Some(6.0));
+            // The extra delay still lands on the forward leg
+            assert_ms(r.forward_ms, 6.8);
+            assert_ms(r.return_ms, 6.0);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use serde::Serialize;
+use std::fmt;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::stats::{StatsReport, StatsReportType};
+
+/// One end of the candidate pair ICE selected.
+#[derive(Debug, Clone, Serialize)]
+pub struct Endpoint {
+    /// Network type, e.g. udp4
+    pub protocol: String,
+    pub ip: String,
+    pub port: u16,
+    /// host, srflx, prflx or relay
+    pub candidate_type: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelectedPair {
+    pub local: Endpoint,
+    pub remote: Endpoint,
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ip.contains(':') {
+            write!(f, "[{}]:{} ({})", self.ip, self.port, self.candidate_type)
+        } else {
+            write!(f, "{}:{} ({})", self.ip, self.port, self.candidate_type)
+        }
+    }
+}
+
+impl fmt::Display for SelectedPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} <-> {}",
+            self.local.protocol, self.local, self.remote
+        )
+    }
+}
+
+/// One candidate pair from the ICE checklist, for --report-candidate-latency.
+#[derive(Debug, Clone, Serialize)]
+pub struct CandidatePair {
+    pub local: Endpoint,
+    pub remote: Endpoint,
+    /// ICE check state, e.g. succeeded
+    pub state: String,
+    pub nominated: bool,
+    /// None where no RTT was measured for the pair
+    pub rtt_ms: Option<f64>,
+}
+
+fn endpoint(stats: &StatsReport, id: &str) -> Option<Endpoint> {
+    match stats.reports.get(id)? {
+        StatsReportType::LocalCandidate(c) | StatsReportType::RemoteCandidate(c) => {
+            Some(Endpoint {
+                protocol: c.network_type.to_string(),
+                ip: c.ip.clone(),
+                port: c.port,
+                candidate_type: c.candidate_type.to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Look up the nominated candidate pair in the connection stats.
+pub async fn selected_pair(pc: &RTCPeerConnection) -> Option<SelectedPair> {
+    let stats = pc.get_stats().await;
+    let pair = stats.reports.values().find_map(|r| match r {
+        StatsReportType::CandidatePair(p) if p.nominated => Some(p),
+        _ => None,
+    })?;
+    Some(SelectedPair {
+        local: endpoint(&stats, &pair.local_candidate_id)?,
+        remote: endpoint(&stats, &pair.remote_candidate_id)?,
+    })
+}
+
+/// Every pair in the ICE checklist, nominated first. webrtc-ice leaves the
+/// per-pair RTT counters at zero, so the nominated pair takes `selected_rtt`
+/// (measured over the data channel) and the others have no RTT.
+pub async fn candidate_pairs(
+    pc: &RTCPeerConnection,
+    selected_rtt: Option<f64>,
+) -> Vec<CandidatePair> {
+    let stats = pc.get_stats().await;
+    let mut pairs: Vec<_> = stats
+        .reports
+        .values()
+        .filter_map(|r| match r {
+            StatsReportType::CandidatePair(p) => Some(p),
+            _ => None,
+        })
+        .filter_map(|p| {
+            Some(CandidatePair {
+                local: endpoint(&stats, &p.local_candidate_id)?,
+                remote: endpoint(&stats, &p.remote_candidate_id)?,
+                state: p.state.to_string(),
+                nominated: p.nominated,
+                rtt_ms: pair_rtt(p.current_round_trip_time, p.nominated, selected_rtt),
+            })
+        })
+        .collect();
+    pairs.sort_by_key(|p| (!p.nominated, p.local.to_string(), p.remote.to_string()));
+    pairs
+}
+
+/// RTT of one pair in ms: the reported one (in seconds) when ICE measured
+/// it, else `selected_rtt` for the nominated pair.
+fn pair_rtt(reported_s: f64, nominated: bool, selected_rtt: Option<f64>) -> Option<f64> {
+    let reported = reported_s * 1000.0;
+    if reported > 0.0 {
+        Some(reported)
+    } else if nominated {
+        selected_rtt
+    } else {
+        None
+    }
+}
+
+/// The candidate pairs, rendered as a table.
+pub struct PairTable<'a>(pub &'a [CandidatePair]);
+
+impl fmt::Display for PairTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "=== Candidate pairs ===")?;
+        if self.0.is_empty() {
+            return writeln!(f, "(none in stats)");
+        }
+        writeln!(
+            f,
+            "{:<3} {:<6} {:<34} {:<34} {:<11} {:>8}",
+            "", "proto", "local", "remote", "state", "rtt ms"
+        )?;
+        for pair in self.0 {
+            writeln!(
+                f,
+                "{:<3} {:<6} {:<34} {:<34} {:<11} {:>8}",
+                if pair.nominated { "*" } else { "" },
+                pair.local.protocol,
+                pair.local.to_string(),
+                pair.remote.to_string(),
+                pair.state,
+                pair.rtt_ms.map_or("-".to_string(), |v| format!("{:.2}", v))
+            )?;
+        }
+        writeln!(
+            f,
+            "* nominated; webrtc-ice does not measure RTT on the other pairs"
+        )
+    }
+}
+
+/// Print the candidate pairs as a table, or one JSON line when `json` is set.
+pub fn print_candidate_pairs(pairs: &[CandidatePair], json: bool) {
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({ "event": "candidate_pairs", "pairs": pairs })
+        );
+        return;
+    }
+    print!("\n{}", PairTable(pairs));
+}
+
+/// Print the selected pair, as a JSON line when `json` is set.
+pub async fn print_selected_pair(pc: &RTCPeerConnection, json: bool) {
+    match selected_pair(pc).await {
+        Some(pair) if json => println!(
+            "{}",
+            serde_json::json!({ "event": "selected_pair", "pair": pair })
+        ),
+        Some(pair) => println!("Selected pair: {}", pair),
+        None => eprintln!("warning: no nominated candidate pair in stats"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(ip: &str, port: u16, candidate_type: &str) -> Endpoint {
+        Endpoint {
+            protocol: "udp4".to_string(),
+            ip: ip.to_string(),
+            port,
+            candidate_type: candidate_type.to_string(),
+        }
+    }
+
+    fn pair(
+        local: Endpoint,
+        remote: Endpoint,
+        nominated: bool,
+        rtt_ms: Option<f64>,
+    ) -> CandidatePair {
+        CandidatePair {
+            local,
+            remote,
+            state: if nominated { "succeeded" } else { "waiting" }.to_string(),
+            nominated,
+            rtt_ms,
+        }
+    }
+
+    #[test]
+    fn only_the_nominated_pair_takes_the_channel_rtt() {
+        assert_eq!(pair_rtt(0.0, true, Some(12.5)), Some(12.5));
+        assert_eq!(pair_rtt(0.0, false, Some(12.5)), None);
+        assert_eq!(pair_rtt(0.0, true, None), None);
+        // A measured RTT wins, converted from seconds
+        assert_eq!(pair_rtt(0.004, false, Some(12.5)), Some(4.0));
+    }
+
+    #[test]
+    fn the_table_has_a_row_per_pair() {
+        let pairs = [
+            pair(
+                endpoint("192.0.2.2", 41000, "host"),
+                endpoint("198.51.100.7", 3478, "srflx"),
+                true,
+                pair_rtt(0.0, true, Some(12.345)),
+            ),
+            pair(
+                endpoint("192.0.2.2", 41000, "host"),
+                endpoint("203.0.113.9", 50000, "relay"),
+                false,
+                pair_rtt(0.0, false, Some(12.345)),
+            ),
+            pair(
+                endpoint("fd00::2", 52087, "host"),
+                endpoint("fd00::7", 52088, "host"),
+                false,
+                None,
+            ),
+        ];
+        let text = PairTable(&pairs).to_string();
+        let lines: Vec<_> = text.lines().collect();
+        // Title, header, three rows, footnote
+        assert_eq!(lines.len(), 6, "{}", text);
+        let rows = &lines[2..5];
+        assert!(rows[0].starts_with("*   udp4"), "{}", text);
+        assert!(rows[0].contains("192.0.2.2:41000 (host)"), "{}", text);
+        assert!(rows[0].contains("198.51.100.7:3478 (srflx)"), "{}", text);
+        assert!(rows[0].contains("succeeded"), "{}", text);
+        assert!(rows[0].ends_with("   12.35"), "{}", text);
+        assert!(rows[1].contains("203.0.113.9:50000 (relay)"), "{}", text);
+        assert!(rows[1].ends_with("       -"), "{}", text);
+        assert!(rows[2].contains("[fd00::2]:52087 (host)"), "{}", text);
+        assert!(rows[2].ends_with("       -"), "{}", text);
+        // Columns line up whatever the addresses
+        let width = rows[0].len();
+        assert!(rows.iter().all(|r| r.len() == width), "{}", text);
+        assert_eq!(
+            PairTable(&[]).to_string(),
+            "=== Candidate pairs ===\n(none in stats)\n"
+        );
+    }
+}
@@ -0,0 +1,553 @@
+use anyhow::{anyhow, Result};
+use std::future::Future;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::{APIBuilder, API};
+use webrtc::error::OnErrorHdlrFn;
+use webrtc::ice::udp_network::{EphemeralUDP, UDPNetwork};
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::cert;
+use crate::cidr::AddressFilter;
+use crate::cli::CommonArgs;
+use crate::failure::{Failure, FailureKind};
+use crate::sdp;
+
+/// Build the WebRTC API from the shared command line options.
+pub fn build_api(args: &CommonArgs) -> Result<API> {
+    let m = MediaEngine::default();
+    let mut s = SettingEngine::default();
+    if let (Some(min), Some(max)) = (args.port_min, args.port_max) {
+        s.set_udp_network(UDPNetwork::Ephemeral(EphemeralUDP::new(min, max)?));
+    }
+    Ok(APIBuilder::new()
+        .with_media_engine(m)
+        .with_setting_engine(s)
+        .build())
+}
+
+/// Used when neither the command line nor the environment names a STUN server.
+const DEFAULT_STUN: &str = "stun:stun.l.google.com:19302";
+
+/// STUN server URLs from the command line or environment.
+pub fn stun_urls(args: &CommonArgs) -> Vec<String> {
+    if args.stun.is_empty() {
+        vec![DEFAULT_STUN.to_string()]
+    } else {
+        args.stun.clone()
+    }
+}
+
+/// ICE servers from the command line or environment.
+pub fn ice_servers(args: &CommonArgs) -> Vec<RTCIceServer> {
+    let mut servers = vec![RTCIceServer {
+        urls: stun_urls(args),
+        ..Default::default()
+    }];
+    if let Some(url) = &args.turn_url {
+        servers.push(RTCIceServer {
+            urls: vec![url.clone()],
+            username: args.turn_user.clone().unwrap_or_default(),
+            credential: args.turn_pass.clone().unwrap_or_default(),
+            ..Default::default()
+        });
+    }
+    servers
+}
+
+pub fn rtc_config(args: &CommonArgs) -> Result<RTCConfiguration> {
+    let mut certificates = Vec::new();
+    if let Some(passphrase) = &args.cert_passphrase {
+        let cert = cert::from_passphrase(passphrase)?;
+        println!("Local fingerprint: {}", cert::fingerprint(&cert));
+        certificates.push(cert);
+    }
+    Ok(RTCConfiguration {
+        ice_servers: ice_servers(args),
+        certificates,
+        ..Default::default()
+    })
+}
+
+/// Largest message webrtc-rs reads in one go on a data channel.
+const WEBRTC_RS_MAX_MESSAGE_SIZE: usize = 65535;
+
+/// Size assumed when the remote SDP omits a=max-message-size (RFC 8841).
+const DEFAULT_REMOTE_MAX_MESSAGE_SIZE: usize = 65536;
+
+/// Largest message both ends can carry: the remote's a=max-message-size,
+/// capped by what webrtc-rs itself can receive.
+pub async fn max_message_size(pc: &RTCPeerConnection) -> usize {
+    let remote = pc
+        .remote_description()
+        .await
+        .and_then(|desc| {
+            desc.sdp.lines().find_map(|line| {
+                line.trim()
+                    .strip_prefix("a=max-message-size:")
+                    .and_then(|v| v.parse::<usize>().ok())
+            })
+        })
+        .unwrap_or(DEFAULT_REMOTE_MAX_MESSAGE_SIZE);
+    // 0 means the remote accepts messages of any size
+    if remote == 0 {
+        WEBRTC_RS_MAX_MESSAGE_SIZE
+    } else {
+        remote.min(WEBRTC_RS_MAX_MESSAGE_SIZE)
+    }
+}
+
+/// Refuse pings of `largest` bytes when the channel carries at most `max`,
+/// since every send would fail.
+pub fn check_message_size(largest: usize, max: usize) -> Result<()> {
+    if largest > max {
+        return Err(anyhow!(
+            "payload size {} exceeds the negotiated max message size of {} bytes",
+            largest,
+            max
+        ));
+    }
+    Ok(())
+}
+
+/// Remove remote candidates the address filter refuses, so ICE never
+/// pairs with them.
+pub fn filter_remote_candidates(desc: &mut RTCSessionDescription, filter: &AddressFilter) {
+    if filter.is_empty() {
+        return;
+    }
+    // webrtc-rs lists each candidate once per component; report it once
+    let mut dropped = Vec::new();
+    desc.sdp = sdp::retain_candidates(&desc.sdp, |c| {
+        let keep = filter.permits(&c.address);
+        if !keep && !dropped.contains(c) {
+            dropped.push(c.clone());
+            println!(
+                "Dropping remote candidate {}:{} ({})",
+                c.address, c.port, c.typ
+            );
+        }
+        keep
+    });
+}
+
+/// Enforce --min-candidates and --require-srflx on the local description
+/// about to be sent, explaining what to check when it falls short.
+pub fn check_gathered(desc: &RTCSessionDescription, args: &CommonArgs) -> Result<()> {
+    // webrtc-rs lists each candidate once per component; count it once
+    let mut usable: Vec<sdp::Candidate> = Vec::new();
+    for c in sdp::candidates(&desc.sdp) {
+        if !c.ip().is_some_and(|ip| ip.is_loopback()) && !usable.contains(&c) {
+            usable.push(c);
+        }
+    }
+    if let Some(min) = args.min_candidates {
+        if (usable.len() as u64) < min {
+            let found: Vec<String> = usable
+                .iter()
+                .map(|c| format!("{}:{} ({})", c.address, c.port, c.typ))
+                .collect();
+            let message = format!(
+                "only {} usable candidate(s) gathered, --min-candidates needs {}: [{}]; check \
+                 that a non-loopback interface is up and that the STUN/TURN servers are \
+                 reachable from this host",
+                usable.len(),
+                min,
+                found.join(", ")
+            );
+            return Err(Failure::new(FailureKind::Candidates, message)
+                .with("gathered", found)
+                .into());
+        }
+    }
+    if args.require_srflx && !usable.iter().any(|c| c.typ == "srflx") {
+        let message = format!(
+            "no server-reflexive candidate gathered, as --require-srflx needs; none of the \
+             STUN servers ({}) answered: check the URLs and that outbound UDP is allowed",
+            stun_urls(args).join(", ")
+        );
+        return Err(Failure::new(FailureKind::Candidates, message)
+            .with("stun", stun_urls(args))
+            .into());
+    }
+    Ok(())
+}
+
+/// Fail fast when the local and remote candidates share no IP version: ICE
+/// only pairs candidates of the same family, so it could never connect.
+/// Silent when either side trickles or hides its addresses behind mDNS.
+pub fn check_ip_versions(local_sdp: &str, remote_sdp: &str) -> Result<()> {
+    let (Some(local), Some(remote)) = (sdp::ip_versions(local_sdp), sdp::ip_versions(remote_sdp))
+    else {
+        return Ok(());
+    };
+    if local.is_empty() || remote.is_empty() || local.iter().any(|v| remote.contains(v)) {
+        return Ok(());
+    }
+    let message = format!(
+        "no common IP version between peers (local: {}, remote: {}); ICE cannot pair them, \
+         so give one side an address of the other's version (or a TURN relay that has one)",
+        local.join("+"),
+        remote.join("+")
+    );
+    Err(Failure::new(FailureKind::AddressFamily, message)
+        .with("local", local)
+        .with("remote", remote)
+        .into())
+}
+
+/// Fail the run through `fail_tx` unless the connection is up `limit`
+/// after the remote description was applied (--connect-timeout).
+pub async fn connect_deadline(
+    pc: Weak<RTCPeerConnection>,
+    limit: Duration,
+    fail_tx: mpsc::Sender<anyhow::Error>,
+    cancel: CancellationToken,
+) {
+    if cancel.run_until_cancelled(sleep(limit)).await.is_none() {
+        return;
+    }
+    let Some(pc) = pc.upgrade() else {
+        return;
+    };
+    let state = pc.connection_state();
+    if matches!(
+        state,
+        RTCPeerConnectionState::New
+            | RTCPeerConnectionState::Connecting
+            | RTCPeerConnectionState::Failed
+    ) {
+        let failure = Failure::new(
+            FailureKind::ConnectTimeout,
+            format!(
+                "not connected within {}s (state {})",
+                limit.as_secs(),
+                state
+            ),
+        )
+        .with("timeout_s", limit.as_secs())
+        .with("state", state.to_string());
+        let _ = fail_tx.send(failure.into()).await;
+    }
+}
+
+/// Data channel error handler that reports the error and fails the run
+/// through `fail_tx`. SCTP-level failures surface here rather than as a
+/// failed send.
+pub fn on_channel_error(fail_tx: mpsc::Sender<anyhow::Error>) -> OnErrorHdlrFn {
+    Box::new(move |e| {
+        let fail_tx = fail_tx.clone();
+        Box::pin(async move {
+            eprintln!("data channel error: {}", e);
+            let failure = Failure::new(
+                FailureKind::ChannelError,
+                format!("data channel error: {}", e),
+            );
+            let _ = fail_tx.send(failure.into()).await;
+        })
+    })
+}
+
+/// Tries given to creating the local description before giving up.
+const CREATE_ATTEMPTS: u32 = 3;
+
+/// Pause before the first retry; doubled after each further failure.
+const CREATE_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Failures from the transport layers under the API (sockets, ICE, DTLS),
+/// which resource pressure can cause and which may clear on a retry. Every
+/// other error is a misuse or misconfiguration that would fail again.
+fn is_transient(e: &webrtc::Error) -> bool {
+    use webrtc::Error;
+    matches!(
+        e,
+        Error::Util(_)
+            | Error::Ice(_)
+            | Error::Dtls(_)
+            | Error::Sctp(_)
+            | Error::Interceptor(_)
+            | Error::MpscSend(_)
+            | Error::ErrClosedPipe
+            | Error::ErrExcessiveRetries
+    )
+}
+
+/// Run `create` (creating the offer or the answer), retrying transient
+/// failures with backoff. The final error says what most likely went wrong.
+async fn create_with_retry<F, Fut>(what: &str, mut create: F) -> Result<RTCSessionDescription>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = webrtc::error::Result<RTCSessionDescription>>,
+{
+    let mut backoff = CREATE_BACKOFF;
+    for attempt in 1.. {
+        let e = match create().await {
+            Ok(desc) => return Ok(desc),
+            Err(e) => e,
+        };
+        if !is_transient(&e) {
+            return Err(anyhow!(
+                "creating the {} failed: {} (not retried; check the peer connection \
+                 configuration)",
+                what,
+                e
+            ));
+        }
+        if attempt == CREATE_ATTEMPTS {
+            return Err(anyhow!(
+                "creating the {} failed after {} attempts: {} (likely causes: the media or \
+                 setting engine configuration, such as the port range, or the host running \
+                 out of sockets)",
+                what,
+                attempt,
+                e
+            ));
+        }
+        eprintln!(
+            "warning: creating the {} failed ({}), retrying in {:?}",
+            what, e, backoff
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+    unreachable!()
+}
+
+/// `create_offer` with bounded retries on transient failures.
+pub async fn create_offer(pc: &RTCPeerConnection) -> Result<RTCSessionDescription> {
+    create_with_retry("offer", || pc.create_offer(None)).await
+}
+
+/// `create_answer` with bounded retries on transient failures.
+pub async fn create_answer(pc: &RTCPeerConnection) -> Result<RTCSessionDescription> {
+    create_with_retry("answer", || pc.create_answer(None)).await
+}
+
+/// Close the connection once all output is written. Close errors, and
+/// panics inside the webrtc-rs teardown, are logged but never override the
+/// outcome of the run.
+pub async fn close_quietly(pc: Arc<RTCPeerConnection>) {
+    match tokio::spawn(async move { pc.close().await }).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("close error: {}", e),
+        Err(e) => eprintln!("close panicked: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failure;
+    use crate::testdata;
+
+    #[test]
+    fn oversized_payload_names_both_sizes() {
+        let err = check_message_size(70_000, WEBRTC_RS_MAX_MESSAGE_SIZE).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "payload size 70000 exceeds the negotiated max message size of 65535 bytes"
+        );
+        assert!(check_message_size(65_535, WEBRTC_RS_MAX_MESSAGE_SIZE).is_ok());
+    }
+
+    fn filtered(deny: &[&str]) -> RTCSessionDescription {
+        let mut desc = testdata::offer();
+        let filter = AddressFilter {
+            allow: Vec::new(),
+            deny: deny.iter().map(|c| c.parse().unwrap()).collect(),
+        };
+        filter_remote_candidates(&mut desc, &filter);
+        desc
+    }
+
+    fn addresses(desc: &RTCSessionDescription) -> Vec<String> {
+        let mut found: Vec<String> = sdp::candidates(&desc.sdp)
+            .into_iter()
+            .map(|c| c.address)
+            .collect();
+        found.dedup();
+        found
+    }
+
+    #[test]
+    fn denied_candidates_are_dropped() {
+        assert_eq!(addresses(&filtered(&[])), ["192.0.2.2", "fd00::2"]);
+        assert_eq!(addresses(&filtered(&["192.0.2.0/24"])), ["fd00::2"]);
+        assert_eq!(addresses(&filtered(&["192.0.2.2/32"])), ["fd00::2"]);
+        assert_eq!(addresses(&filtered(&["192.0.2.3/32"])).len(), 2);
+        assert_eq!(addresses(&filtered(&["fd00::/8"])), ["192.0.2.2"]);
+        assert!(addresses(&filtered(&["0.0.0.0/0", "::/0"])).is_empty());
+
+        let desc = filtered(&["0.0.0.0/0"]);
+        assert_eq!(addresses(&desc), ["fd00::2"]);
+        assert!(desc.unmarshal().is_ok());
+        assert!(desc.sdp.contains("a=end-of-candidates"));
+    }
+
+    #[tokio::test]
+    async fn channel_errors_fail_the_run() {
+        let (fail_tx, mut fail_rx) = mpsc::channel(1);
+        let mut handler = on_channel_error(fail_tx);
+        handler(webrtc::Error::ErrClosedPipe).await;
+        let err = fail_rx.try_recv().unwrap();
+        let json = failure::to_json(&err);
+        assert_eq!(json["kind"], "channel_error");
+        assert!(
+            err.to_string().starts_with("data channel error: "),
+            "{}",
+            err
+        );
+    }
+
+    /// A stand-in for `create_offer` that fails with `errors` in turn and
+    /// then succeeds, counting its calls.
+    fn flaky(
+        errors: Vec<webrtc::Error>,
+        calls: &std::cell::Cell<u32>,
+    ) -> impl FnMut() -> std::future::Ready<webrtc::error::Result<RTCSessionDescription>> + '_ {
+        let mut errors = errors.into_iter();
+        move || {
+            calls.set(calls.get() + 1);
+            std::future::ready(match errors.next() {
+                Some(e) => Err(e),
+                None => Ok(testdata::offer()),
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_failures_are_retried() {
+        let calls = std::cell::Cell::new(0);
+        let errors = vec![webrtc::Error::ErrClosedPipe];
+        let desc = create_with_retry("offer", flaky(errors, &calls))
+            .await
+            .unwrap();
+        assert_eq!(desc.sdp, testdata::OFFER_SDP);
+        assert_eq!(calls.get(), 2);
+
+        let calls = std::cell::Cell::new(0);
+        let errors = (0..CREATE_ATTEMPTS)
+            .map(|_| webrtc::Error::ErrClosedPipe)
+            .collect();
+        let err = create_with_retry("offer", flaky(errors, &calls))
+            .await
+            .unwrap_err();
+        assert_eq!(calls.get(), CREATE_ATTEMPTS);
+        assert!(err.to_string().contains("after 3 attempts"), "{}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn other_failures_fail_at_once() {
+        let calls = std::cell::Cell::new(0);
+        let errors = vec![webrtc::Error::ErrConnectionClosed];
+        let start = tokio::time::Instant::now();
+        let err = create_with_retry("answer", flaky(errors, &calls))
+            .await
+            .unwrap_err();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert!(err.to_string().contains("(not retried"), "{}", err);
+    }
+
+    fn loopback_only() -> RTCSessionDescription {
+        testdata::offer_with(|sdp| {
+            sdp.replace("192.0.2.2", "127.0.0.1")
+                .replace("fd00::2", "::1")
+        })
+    }
+
+    #[test]
+    fn loopback_candidates_do_not_count() {
+        let args = testdata::common_args(&["--min-candidates", "1"]);
+        let err = check_gathered(&loopback_only(), &args).unwrap_err();
+        assert_eq!(failure::to_json(&err)["kind"], "candidates");
+        assert_eq!(
+            err.to_string(),
+            "only 0 usable candidate(s) gathered, --min-candidates needs 1: []; check that a \
+             non-loopback interface is up and that the STUN/TURN servers are reachable from \
+             this host"
+        );
+
+        // Each candidate counts once, not once per component
+        let two = testdata::common_args(&["--min-candidates", "2"]);
+        assert!(check_gathered(&testdata::offer(), &two).is_ok());
+        let three = testdata::common_args(&["--min-candidates", "3"]);
+        let err = check_gathered(&testdata::offer(), &three).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("[192.0.2.2:33022 (host), fd00::2:52087 (host)]"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn missing_srflx_names_the_stun_servers() {
+        let args = testdata::common_args(&["--require-srflx", "--stun", "stun:stun.example:3478"]);
+        let err = check_gathered(&testdata::offer(), &args).unwrap_err();
+        assert_eq!(failure::to_json(&err)["kind"], "candidates");
+        assert!(
+            err.to_string()
+                .contains("none of the STUN servers (stun:stun.example:3478)"),
+            "{}",
+            err
+        );
+        let srflx = testdata::offer_with(|sdp| {
+            sdp.replace(
+                "192.0.2.2 33022 typ host",
+                "203.0.113.5 40000 typ srflx raddr 192.0.2.2 rport 33022",
+            )
+        });
+        assert!(check_gathered(&srflx, &args).is_ok());
+    }
+
+    /// [`testdata::OFFER_SDP`] without the candidates that mention `ip`.
+    fn without_candidates_on(ip: &str) -> String {
+        testdata::OFFER_SDP
+            .split_inclusive("\r\n")
+            .filter(|l| !(l.starts_with("a=candidate:") && l.contains(ip)))
+            .collect()
+    }
+
+    #[test]
+    fn disjoint_ip_versions_fail_fast() {
+        let v4_only = without_candidates_on("fd00::2");
+        let v6_only = without_candidates_on("192.0.2.2");
+        let err = check_ip_versions(&v6_only, &v4_only).unwrap_err();
+        let json = failure::to_json(&err);
+        assert_eq!(json["kind"], "address_family");
+        assert_eq!(json["context"]["local"], serde_json::json!(["v6"]));
+        assert_eq!(json["context"]["remote"], serde_json::json!(["v4"]));
+        assert!(
+            err.to_string()
+                .starts_with("no common IP version between peers (local: v6, remote: v4)"),
+            "{}",
+            err
+        );
+        let err = check_ip_versions(&v4_only, &v6_only).unwrap_err();
+        assert!(
+            err.to_string().contains("(local: v4, remote: v6)"),
+            "{}",
+            err
+        );
+
+        // One version in common is enough
+        let both = testdata::OFFER_SDP;
+        assert!(check_ip_versions(both, &v4_only).is_ok());
+        assert!(check_ip_versions(&v6_only, both).is_ok());
+        // Nothing to go on while a side trickles or uses mDNS
+        let trickling = without_candidates_on("");
+        assert!(check_ip_versions(&v6_only, &trickling).is_ok());
+        let mdns = v4_only.replace("192.0.2.2", "1f0c5b1e-6e2d-4c1b-9f0e-3d7a2b8c4e51.local");
+        assert!(check_ip_versions(&v6_only, &mdns).is_ok());
+    }
+}
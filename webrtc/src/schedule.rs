@@ -0,0 +1,116 @@
+//! Send schedules: recorded with --record-schedule, replayed with --replay
+//! to reproduce a traffic pattern exactly.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::wire;
+
+/// One ping of a schedule.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ScheduledSend {
+    /// Microseconds after the first send
+    pub at_us: u64,
+    /// Message size in bytes, header included
+    pub size: usize,
+}
+
+impl ScheduledSend {
+    pub fn at(&self) -> Duration {
+        Duration::from_micros(self.at_us)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    pub sends: Vec<ScheduledSend>,
+    /// Session time of the first recorded send
+    #[serde(skip)]
+    origin: Option<Duration>,
+}
+
+impl Schedule {
+    /// Load a recorded schedule, checking it can be replayed as framed pings.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let schedule: Schedule =
+            serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        if schedule.sends.is_empty() {
+            bail!("{} holds no sends", path.display());
+        }
+        for (i, pair) in schedule.sends.windows(2).enumerate() {
+            if pair[1].at_us < pair[0].at_us {
+                bail!(
+                    "{}: send {} is earlier than the one before it",
+                    path.display(),
+                    i + 2
+                );
+            }
+        }
+        if let Some(send) = schedule
+            .sends
+            .iter()
+            .find(|s| s.size < wire::PING_HEADER_LEN)
+        {
+            bail!(
+                "{}: a {}-byte send is smaller than the {}-byte ping header",
+                path.display(),
+                send.size,
+                wire::PING_HEADER_LEN
+            );
+        }
+        Ok(schedule)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Note a send made at session time `now`.
+    pub fn record(&mut self, now: Duration, size: usize) {
+        let origin = *self.origin.get_or_insert(now);
+        self.sends.push(ScheduledSend {
+            at_us: now.saturating_sub(origin).as_micros() as u64,
+            size,
+        });
+    }
+
+    pub fn largest(&self) -> usize {
+        self.sends.iter().map(|s| s.size).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_recorded_schedule_loads_back_with_its_spacing() {
+        let mut schedule = Schedule::default();
+        // Times are session times; the schedule counts from the first send
+        let sends = [(1_500, 64), (2_500, 64), (2_750, 1200), (4_000, 17)];
+        for (ms, size) in sends {
+            schedule.record(Duration::from_millis(ms), size);
+        }
+        let path = std::env::temp_dir().join(format!("schedule-{}.json", std::process::id()));
+        schedule.save(&path).unwrap();
+        let loaded = Schedule::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.sends.len(), sends.len());
+        let at: Vec<_> = loaded.sends.iter().map(|s| s.at()).collect();
+        assert_eq!(
+            at,
+            [0, 1_000, 1_250, 2_500].map(Duration::from_millis).to_vec()
+        );
+        let sizes: Vec<_> = loaded.sends.iter().map(|s| s.size).collect();
+        assert_eq!(sizes, [64, 64, 1200, 17]);
+        assert_eq!(loaded.largest(), 1200);
+    }
+}
@@ -0,0 +1,205 @@
+//! Line-level helpers over SDP text.
+
+use std::net::IpAddr;
+
+/// The fields of an `a=candidate` line this crate cares about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// IP address, or an mDNS hostname for obfuscated host candidates
+    pub address: String,
+    pub port: u16,
+    /// host, srflx, prflx or relay
+    pub typ: String,
+}
+
+impl Candidate {
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.address.parse().ok()
+    }
+}
+
+/// Parse an `a=candidate:` line (or the bare `candidate:` form used in
+/// trickle messages).
+pub fn parse_candidate(line: &str) -> Option<Candidate> {
+    let line = line.trim();
+    let rest = line
+        .strip_prefix("a=candidate:")
+        .or_else(|| line.strip_prefix("candidate:"))?;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    // foundation component transport priority address port "typ" type ...
+    if fields.len() < 8 || fields[6] != "typ" {
+        return None;
+    }
+    Some(Candidate {
+        address: fields[4].to_string(),
+        port: fields[5].parse().ok()?,
+        typ: fields[7].to_string(),
+    })
+}
+
+/// All candidates listed in an SDP.
+pub fn candidates(sdp: &str) -> Vec<Candidate> {
+    sdp.lines().filter_map(parse_candidate).collect()
+}
+
+/// Drop candidate lines for which `keep` returns false, leaving every
+/// other line untouched.
+pub fn retain_candidates(sdp: &str, mut keep: impl FnMut(&Candidate) -> bool) -> String {
+    let mut out = String::with_capacity(sdp.len());
+    for line in sdp.split_inclusive('\n') {
+        if let Some(c) = parse_candidate(line) {
+            if !keep(&c) {
+                continue;
+            }
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+/// The first `a=fingerprint:` value, e.g. `sha-256 AB:CD:...`.
+pub fn fingerprint(sdp: &str) -> Option<String> {
+    sdp.lines()
+        .find_map(|l| l.trim().strip_prefix("a=fingerprint:"))
+        .map(str::to_string)
+}
+
+/// Drop every `a=candidate` line and `a=end-of-candidates`, leaving a
+/// description for a peer that trickles all its candidates.
+pub fn strip_candidates(sdp: &str) -> String {
+    sdp.split_inclusive('\n')
+        .filter(|line| {
+            let line = line.trim();
+            !line.starts_with("a=candidate:") && line != "a=end-of-candidates"
+        })
+        .collect()
+}
+
+/// The first `a=mid:` value, naming the media section candidates belong to.
+pub fn first_mid(sdp: &str) -> Option<String> {
+    sdp.lines()
+        .find_map(|l| l.trim().strip_prefix("a=mid:"))
+        .map(str::to_string)
+}
+
+/// IP versions of the candidates listed in an SDP, as `v4`/`v6`. `None` when
+/// a candidate is an mDNS hostname, which could resolve to either.
+pub fn ip_versions(sdp: &str) -> Option<Vec<&'static str>> {
+    let mut versions = Vec::new();
+    for c in candidates(sdp) {
+        let version = match c.ip()? {
+            IpAddr::V4(_) => "v4",
+            IpAddr::V6(_) => "v6",
+        };
+        if !versions.contains(&version) {
+            versions.push(version);
+        }
+    }
+    versions.sort_unstable();
+    Some(versions)
+}
+
+/// Rewrite an SDP into a canonical form (--canonicalize-sdp), so that
+/// descriptions differing only in candidate order or whitespace come out
+/// as the same text:
+///
+/// - lines end in CRLF and lose trailing whitespace; blank lines go;
+/// - the fields of `a=candidate` lines are separated by single spaces;
+/// - each section's candidates are sorted among the lines they occupy.
+///
+/// Canonicalizing must never change what the SDP means, so nothing else
+/// moves: ICE ranks candidates by their priority field rather than their
+/// position, but the order of other lines can matter (RFC 8866 fixes the
+/// order of session-level lines, and media formats are listed by
+/// preference).
+pub fn canonicalize(sdp: &str) -> String {
+    let mut out = String::with_capacity(sdp.len());
+    let mut section = Vec::new();
+    for line in sdp.lines().map(str::trim_end).filter(|l| !l.is_empty()) {
+        if line.starts_with("m=") {
+            push_canonical(&mut out, &section);
+            section.clear();
+        }
+        if line.starts_with("a=candidate:") {
+            section.push(line.split_whitespace().collect::<Vec<_>>().join(" "));
+        } else {
+            section.push(line.to_string());
+        }
+    }
+    push_canonical(&mut out, &section);
+    out
+}
+
+/// Write one section with its candidate lines sorted.
+fn push_canonical(out: &mut String, section: &[String]) {
+    let is_candidate = |l: &String| l.starts_with("a=candidate:");
+    let mut candidates: Vec<&String> = section.iter().filter(|l| is_candidate(l)).collect();
+    candidates.sort();
+    let mut sorted = candidates.into_iter();
+    for line in section {
+        let line = match is_candidate(line) {
+            true => sorted.next().unwrap_or(line),
+            false => line,
+        };
+        out.push_str(line);
+        out.push_str("\r\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{signal, testdata, trickle};
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+    #[test]
+    fn stripped_sdp_has_no_candidates_and_still_parses() {
+        let stripped = strip_candidates(testdata::OFFER_SDP);
+        assert!(!stripped.lines().any(|l| l.starts_with("a=candidate")));
+        assert!(!stripped.contains("a=end-of-candidates"));
+        assert_eq!(
+            stripped.lines().count(),
+            testdata::OFFER_SDP.lines().count() - 5
+        );
+        assert!(stripped.ends_with("a=ice-pwd:FLvevoeZDlBIOTgqBTKHBqrwwQOzAMtx\r\n"));
+        assert!(candidates(&stripped).is_empty());
+
+        let mut desc = testdata::offer();
+        trickle::strip_candidates(&mut desc).unwrap();
+        assert_eq!(desc.sdp, stripped);
+        let media = &desc.unmarshal().unwrap().media_descriptions[0];
+        assert!(media.attribute("candidate").is_none());
+        assert_eq!(media.attribute("mid"), Some(Some("0")));
+
+        // Bare line feeds are kept as they are
+        let lf = testdata::OFFER_SDP.replace("\r\n", "\n");
+        assert_eq!(strip_candidates(&lf), stripped.replace("\r\n", "\n"));
+    }
+
+    fn blob(sdp: &str) -> String {
+        signal::encode_sdp(&RTCSessionDescription::offer(canonicalize(sdp)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn equivalent_sdps_give_the_same_blob() {
+        // The candidates in another order, with odd spacing, bare line
+        // feeds, trailing whitespace and a blank line
+        let mut lines: Vec<String> = testdata::OFFER_SDP.lines().map(str::to_string).collect();
+        lines[14..18].reverse();
+        lines[15] = lines[15].replace(' ', "  ");
+        lines[7].push_str(" \t");
+        lines.insert(4, String::new());
+        let messy = lines.join("\n") + "\n";
+        assert_ne!(messy, testdata::OFFER_SDP);
+
+        let canonical = canonicalize(&messy);
+        assert_eq!(canonical, canonicalize(testdata::OFFER_SDP));
+        assert_eq!(blob(&messy), blob(testdata::OFFER_SDP));
+        assert_eq!(canonicalize(&canonical), canonical);
+
+        // Other lines keep their order, since it can carry meaning
+        let mut moved: Vec<&str> = testdata::OFFER_SDP.lines().collect();
+        moved.swap(8, 9);
+        assert_ne!(blob(&moved.join("\r\n")), blob(testdata::OFFER_SDP));
+    }
+}
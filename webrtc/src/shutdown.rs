@@ -0,0 +1,71 @@
+//! Orderly shutdown of the tasks a run spawns.
+
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// How long shutdown waits for cancelled tasks to finish.
+pub const GRACE: Duration = Duration::from_secs(2);
+
+/// Cancel every task and wait for those spawned on `tasks` to finish, so
+/// none of them outlives the run or prints after its summary.
+pub async fn stop(cancel: &CancellationToken, tasks: &TaskTracker) {
+    cancel.cancel();
+    tasks.close();
+    if timeout(GRACE, tasks.wait()).await.is_err() {
+        eprintln!(
+            "warning: {} task(s) still running {:?} after shutdown",
+            tasks.len(),
+            GRACE
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::time::{sleep, Instant};
+
+    #[tokio::test(start_paused = true)]
+    async fn tracked_tasks_stop_on_cancel() {
+        let cancel = CancellationToken::new();
+        let tasks = TaskTracker::new();
+        let finished = Arc::new(AtomicUsize::new(0));
+        for _ in 0..3 {
+            let (cancel, finished) = (cancel.clone(), Arc::clone(&finished));
+            tasks.spawn(async move {
+                loop {
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = sleep(Duration::from_millis(100)) => {}
+                    }
+                }
+                // Some cleanup on the way out
+                sleep(Duration::from_millis(50)).await;
+                finished.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        sleep(Duration::from_secs(1)).await;
+
+        let start = Instant::now();
+        stop(&cancel, &tasks).await;
+        assert!(start.elapsed() < GRACE, "{:?}", start.elapsed());
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+        assert!(tasks.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_stuck_task_holds_shutdown_only_for_the_grace() {
+        let cancel = CancellationToken::new();
+        let tasks = TaskTracker::new();
+        tasks.spawn(sleep(Duration::from_secs(3600)));
+
+        let start = Instant::now();
+        stop(&cancel, &tasks).await;
+        assert_eq!(start.elapsed(), GRACE);
+        assert_eq!(tasks.len(), 1);
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use std::io;
+use tokio::sync::mpsc;
+
+use crate::failure::{Failure, FailureKind};
+use crate::sdp;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+/// Encode a session description as the blob peers copy/paste.
+///
+/// Blobs are always written with the standard alphabet and padding; the
+/// decoder also accepts url-safe and unpadded variants so blobs that went
+/// through url-safe transports still work.
+pub fn encode_sdp(desc: &RTCSessionDescription) -> Result<String> {
+    let json = serde_json::to_string(desc)?;
+    Ok(STANDARD.encode(json))
+}
+
+/// Canonicalize the SDP of a description about to be encoded.
+pub fn canonicalize(desc: &mut RTCSessionDescription) -> Result<()> {
+    desc.sdp = sdp::canonicalize(&desc.sdp);
+    desc.unmarshal()
+        .context("SDP no longer parses after --canonicalize-sdp")?;
+    Ok(())
+}
+
+/// Decode a pasted blob back into a session description.
+pub fn decode_sdp(blob: &str) -> Result<RTCSessionDescription> {
+    let decoded = decode_base64(blob).and_then(|bytes| {
+        let json = String::from_utf8(bytes)?;
+        Ok(serde_json::from_str(&json)?)
+    });
+    decoded.map_err(|e| Failure::new(FailureKind::SdpParse, format!("{:#}", e)).into())
+}
+
+/// Decode base64 trying standard, url-safe, then unpadded variants.
+pub fn decode_base64(blob: &str) -> Result<Vec<u8>> {
+    let blob: String = blob.split_whitespace().collect();
+    for engine in [&STANDARD, &URL_SAFE, &STANDARD_NO_PAD, &URL_SAFE_NO_PAD] {
+        if let Ok(bytes) = engine.decode(&blob) {
+            return Ok(bytes);
+        }
+    }
+    Err(anyhow!(
+        "pasted blob is not valid base64 (tried standard, url-safe and unpadded variants)"
+    ))
+}
+
+/// Read stdin lines on a plain thread: tokio's stdin would hold up
+/// shutdown on Ctrl+C until the next line arrives.
+pub fn stdin_lines() -> mpsc::Receiver<io::Result<String>> {
+    let (tx, rx) = mpsc::channel(1);
+    std::thread::spawn(move || {
+        for line in io::stdin().lines() {
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testdata;
+
+    #[test]
+    fn every_base64_variant_decodes_alike() {
+        // A session name that puts '+', '/' and padding into the encoding
+        let offer = testdata::offer_with(|sdp| sdp.replace("s=-", "s=latency ~~~???"));
+        let json = serde_json::to_string(&offer).unwrap();
+        let standard = STANDARD.encode(&json);
+        assert_eq!(encode_sdp(&offer).unwrap(), standard);
+        let variants = [
+            standard.clone(),
+            URL_SAFE.encode(&json),
+            STANDARD_NO_PAD.encode(&json),
+            URL_SAFE_NO_PAD.encode(&json),
+        ];
+        assert!(standard.contains(['+', '/']) && standard.ends_with('='));
+        for variant in variants {
+            // As wrapped by mail clients and terminals
+            let wrapped: Vec<&str> = variant
+                .as_bytes()
+                .chunks(76)
+                .map(|c| std::str::from_utf8(c).unwrap())
+                .collect();
+            let pasted = format!("  {}\r\n", wrapped.join("\n"));
+            assert_eq!(
+                decode_base64(&pasted).unwrap(),
+                json.as_bytes(),
+                "{}",
+                variant
+            );
+            assert_eq!(decode_sdp(&pasted).unwrap().sdp, offer.sdp);
+        }
+    }
+
+    #[test]
+    fn garbage_is_an_sdp_parse_failure() {
+        for blob in ["garbage!", "aGVsbG8="] {
+            let err = decode_sdp(blob).unwrap_err();
+            assert_eq!(crate::failure::to_json(&err)["kind"], "sdp_parse");
+        }
+    }
+}
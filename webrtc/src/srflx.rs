@@ -0,0 +1,226 @@
+//! Watch our server-reflexive mapping for changes during a session.
+//!
+//! webrtc-rs gathers candidates once, so a NAT rebinding never shows up in
+//! the ICE candidates themselves. Two things are polled instead: the local
+//! srflx candidates and selected pair in the connection stats, and a fresh
+//! STUN binding from a socket kept open for the whole session, whose
+//! mapping a rebinding NAT changes just like the ICE socket's.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Weak;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use webrtc::ice::candidate::CandidateType;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::stats::StatsReportType;
+
+use crate::{nat, path};
+
+/// How often the mapping is checked.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Observed {
+    srflx: BTreeSet<String>,
+    selected_local: Option<String>,
+    probed: Option<SocketAddr>,
+}
+
+async fn observe(pc: &RTCPeerConnection, probe: Option<&(UdpSocket, SocketAddr)>) -> Observed {
+    let stats = pc.get_stats().await;
+    let srflx = stats
+        .reports
+        .values()
+        .filter_map(|r| match r {
+            StatsReportType::LocalCandidate(c)
+                if c.candidate_type == CandidateType::ServerReflexive =>
+            {
+                Some(format!("{}:{}", c.ip, c.port))
+            }
+            _ => None,
+        })
+        .collect();
+    let selected_local = path::selected_pair(pc).await.map(|p| p.local.to_string());
+    let probed = match probe {
+        Some((socket, server)) => nat::mapped_address(socket, *server).await,
+        None => None,
+    };
+    Observed {
+        srflx,
+        selected_local,
+        probed,
+    }
+}
+
+/// One mapping that differs between two polls.
+#[derive(Debug, PartialEq)]
+struct Change {
+    what: &'static str,
+    old: String,
+    new: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} changed: {} -> {}", self.what, self.old, self.new)
+    }
+}
+
+impl Change {
+    fn warn(&self, json: bool) {
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "event": "srflx_change",
+                    "what": self.what,
+                    "old": self.old,
+                    "new": self.new,
+                })
+            );
+        } else {
+            eprintln!("warning: {}", self);
+        }
+    }
+}
+
+/// Every mapping that changed from `old` to `now`. Values missing from
+/// either poll are not compared.
+fn changes(old: &Observed, now: &Observed) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if old.srflx != now.srflx {
+        changes.push(Change {
+            what: "srflx candidates",
+            old: list(&old.srflx),
+            new: list(&now.srflx),
+        });
+    }
+    if let (Some(a), Some(b)) = (&old.selected_local, &now.selected_local) {
+        if a != b {
+            changes.push(Change {
+                what: "selected local candidate",
+                old: a.clone(),
+                new: b.clone(),
+            });
+        }
+    }
+    if let (Some(a), Some(b)) = (old.probed, now.probed) {
+        if a != b {
+            changes.push(Change {
+                what: "reflexive mapping",
+                old: a.to_string(),
+                new: b.to_string(),
+            });
+        }
+    }
+    changes
+}
+
+fn list(set: &BTreeSet<String>) -> String {
+    if set.is_empty() {
+        "none".to_string()
+    } else {
+        set.iter().cloned().collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Poll until the connection is dropped, warning whenever a mapping
+/// changes. `stun` names the servers to probe; the first that resolves is
+/// used.
+pub async fn watch(
+    pc: Weak<RTCPeerConnection>,
+    stun: Vec<String>,
+    json: bool,
+    cancel: CancellationToken,
+) {
+    let mut probe = None;
+    for url in &stun {
+        if let Some(server) = nat::resolve(url).await {
+            if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
+                probe = Some((socket, server));
+                break;
+            }
+        }
+    }
+
+    let mut last: Option<Observed> = None;
+    loop {
+        let Some(pc) = pc.upgrade() else {
+            return;
+        };
+        let now = observe(&pc, probe.as_ref()).await;
+        drop(pc);
+        if let Some(old) = &last {
+            for change in changes(old, &now) {
+                change.warn(json);
+            }
+        }
+        // Keep the last known values across a probe that went unanswered
+        last = Some(match last {
+            Some(old) => Observed {
+                selected_local: now.selected_local.or(old.selected_local),
+                probed: now.probed.or(old.probed),
+                srflx: now.srflx,
+            },
+            None => now,
+        });
+        tokio::select! {
+            _ = sleep(CHECK_INTERVAL) => {}
+            _ = cancel.cancelled() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observed(srflx: &[&str], selected: &str, probed: Option<&str>) -> Observed {
+        Observed {
+            srflx: srflx.iter().map(|s| s.to_string()).collect(),
+            selected_local: Some(selected.to_string()),
+            probed: probed.map(|p| p.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn a_rebinding_is_warned_about() {
+        let before = observed(
+            &["203.0.113.5:40000"],
+            "203.0.113.5:40000 (srflx)",
+            Some("203.0.113.5:40002"),
+        );
+        assert_eq!(changes(&before, &before), []);
+
+        let after = observed(
+            &["203.0.113.5:41000"],
+            "203.0.113.5:41000 (srflx)",
+            Some("203.0.113.5:41002"),
+        );
+        let warnings: Vec<String> = changes(&before, &after)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "srflx candidates changed: 203.0.113.5:40000 -> 203.0.113.5:41000",
+                "selected local candidate changed: 203.0.113.5:40000 (srflx) -> \
+                 203.0.113.5:41000 (srflx)",
+                "reflexive mapping changed: 203.0.113.5:40002 -> 203.0.113.5:41002",
+            ]
+        );
+
+        // An unanswered probe is no change
+        let unanswered = observed(&["203.0.113.5:40000"], "203.0.113.5:40000 (srflx)", None);
+        assert_eq!(changes(&before, &unanswered), []);
+        let lost = observed(&[], "203.0.113.5:40000 (srflx)", Some("203.0.113.5:40002"));
+        assert_eq!(
+            changes(&before, &lost)[0].to_string(),
+            "srflx candidates changed: 203.0.113.5:40000 -> none"
+        );
+    }
+}
@@ -0,0 +1,766 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+use crate::aimd::RateReport;
+use crate::ci::{self, BootstrapReport, Interval};
+use crate::oneway::OneWayReport;
+
+/// Pings still unanswered this many sequence numbers behind the newest
+/// echo are counted as lost.
+pub const LOSS_WINDOW: u64 = 8;
+
+/// Two deliveries closer than this are treated as one burst released by
+/// the same SCTP reassembly.
+pub const HOL_BURST_GAP: Duration = Duration::from_millis(1);
+
+/// One echoed ping.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Sample {
+    pub seq: u64,
+    /// Seconds since the session clock started, at arrival
+    pub at_s: f64,
+    pub rtt_ms: f64,
+}
+
+/// Running RTT and loss accounting for one channel.
+#[derive(Debug, Default)]
+pub struct Stats {
+    sent: u64,
+    lost: u64,
+    late: u64,
+    samples: Vec<Sample>,
+    /// seq -> send time on the session clock
+    in_flight: BTreeMap<u64, Duration>,
+    newest_echo: Option<u64>,
+    newest_sent: Option<u64>,
+    /// Pings unanswered for longer than this are counted as lost
+    match_timeout: Option<Duration>,
+    paused: Duration,
+    paused_since: Option<Duration>,
+    hol: Option<HolEstimator>,
+    gaps: Option<GapLog>,
+    window: Option<Window>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also estimate head-of-line blocking, for ordered channels.
+    pub fn with_hol_estimate(mut self) -> Self {
+        self.hol = Some(HolEstimator::default());
+        self
+    }
+
+    /// Also count a ping as lost once it has waited `timeout` for its echo,
+    /// so the in-flight map stays bounded when echoes stop altogether.
+    pub fn with_match_timeout(mut self, timeout: Duration) -> Self {
+        self.match_timeout = Some(timeout);
+        self
+    }
+
+    /// Also record which sequence numbers were lost, see [`Stats::take_gaps`].
+    pub fn with_gap_log(mut self) -> Self {
+        self.gaps = Some(GapLog::default());
+        self
+    }
+
+    /// Also keep a rolling view of the last `span`, see
+    /// [`Stats::window_summary`].
+    pub fn with_window(mut self, span: Duration) -> Self {
+        self.window = Some(Window::new(span));
+        self
+    }
+
+    pub fn on_sent(&mut self, seq: u64, at: Duration) {
+        self.sent += 1;
+        if let Some(window) = &mut self.window {
+            window.on_sent(at);
+        }
+        self.in_flight.insert(seq, at);
+        self.newest_sent = Some(self.newest_sent.map_or(seq, |n| n.max(seq)));
+        self.evict_stale(at);
+    }
+
+    /// Account for the echo of `seq`. Returns the sample, or `None` when the
+    /// ping was already answered or already written off as lost.
+    pub fn on_echo(&mut self, seq: u64, sent: Duration, now: Duration) -> Option<Sample> {
+        self.evict_stale(now);
+        if self.in_flight.remove(&seq).is_none() {
+            self.late += 1;
+            return None;
+        }
+        let sample = Sample {
+            seq,
+            at_s: now.as_secs_f64(),
+            rtt_ms: now.saturating_sub(sent).as_secs_f64() * 1000.0,
+        };
+        self.samples.push(sample);
+        if let Some(window) = &mut self.window {
+            window.on_sample(sample);
+        }
+        if let Some(hol) = &mut self.hol {
+            hol.on_delivery(sent, now);
+        }
+        self.newest_echo = Some(self.newest_echo.map_or(seq, |n| n.max(seq)));
+        self.expire();
+        Some(sample)
+    }
+
+    /// Record an RTT from a legacy ping, which carries no sequence number.
+    pub fn on_legacy_echo(&mut self, rtt: Duration, now: Duration) -> Sample {
+        let sample = Sample {
+            seq: self.samples.len() as u64,
+            at_s: now.as_secs_f64(),
+            rtt_ms: rtt.as_secs_f64() * 1000.0,
+        };
+        self.sent += 1;
+        self.samples.push(sample);
+        if let Some(window) = &mut self.window {
+            window.on_sent(now.saturating_sub(rtt));
+            window.on_sample(sample);
+        }
+        sample
+    }
+
+    /// Write off pings that fell out of the in-flight window.
+    fn expire(&mut self) {
+        let Some(newest) = self.newest_echo else {
+            return;
+        };
+        let cutoff = newest.saturating_sub(LOSS_WINDOW);
+        let keep = self.in_flight.split_off(&cutoff);
+        let lost = std::mem::replace(&mut self.in_flight, keep);
+        self.write_off(lost);
+    }
+
+    /// Write off pings that waited longer than the match timeout.
+    fn evict_stale(&mut self, now: Duration) {
+        let Some(timeout) = self.match_timeout else {
+            return;
+        };
+        // Sequence numbers are sent in order, so the stale ones come first
+        let fresh = self
+            .in_flight
+            .iter()
+            .find(|(_, sent)| now.saturating_sub(**sent) <= timeout)
+            .map(|(seq, _)| *seq);
+        let keep = match fresh {
+            Some(fresh) => self.in_flight.split_off(&fresh),
+            None => BTreeMap::new(),
+        };
+        let lost = std::mem::replace(&mut self.in_flight, keep);
+        self.write_off(lost);
+    }
+
+    /// Count pings removed from the in-flight map as lost. Each seq leaves
+    /// the map once, by whichever rule sees it first, so none is counted twice.
+    fn write_off(&mut self, lost: BTreeMap<u64, Duration>) {
+        if lost.is_empty() {
+            return;
+        }
+        self.lost += lost.len() as u64;
+        if let Some(window) = &mut self.window {
+            window.on_lost(lost.values().copied());
+        }
+        if let Some(gaps) = &mut self.gaps {
+            for (seq, sent) in lost {
+                gaps.on_lost(seq, sent);
+            }
+            // Everything below the oldest ping still in flight is settled
+            let settled = self
+                .in_flight
+                .keys()
+                .next()
+                .copied()
+                .or(self.newest_sent.map(|n| n + 1));
+            if let Some(settled) = settled {
+                gaps.close_before(settled);
+            }
+        }
+    }
+
+    /// Gaps finalized since the last call. A gap stays open while the ping
+    /// after it could still be written off too.
+    pub fn take_gaps(&mut self) -> Vec<Gap> {
+        self.gaps
+            .as_mut()
+            .map_or_else(Vec::new, |gaps| std::mem::take(&mut gaps.closed))
+    }
+
+    /// Write off every ping still in flight, for the end of a bounded run
+    /// once its last echoes have had time to arrive.
+    pub fn finish(&mut self) {
+        let lost = std::mem::take(&mut self.in_flight);
+        self.write_off(lost);
+    }
+
+    /// Every remaining gap, including one still open, for the end of a run.
+    pub fn finish_gaps(&mut self) -> Vec<Gap> {
+        if let Some(gaps) = &mut self.gaps {
+            gaps.closed.extend(gaps.open.take());
+        }
+        self.take_gaps()
+    }
+
+    /// The ping generator stopped sending. Nothing is in flight for the
+    /// pause itself, so it adds no loss; it is only reported.
+    pub fn on_pause(&mut self, at: Duration) {
+        self.paused_since.get_or_insert(at);
+    }
+
+    pub fn on_resume(&mut self, at: Duration) {
+        if let Some(since) = self.paused_since.take() {
+            self.paused += at.saturating_sub(since);
+        }
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// Statistics over the last window only, or `None` without
+    /// [`Stats::with_window`]. [`Stats::summary`] still covers the whole run.
+    pub fn window_summary(&mut self, now: Duration) -> Option<SummaryReport> {
+        let in_flight = self.in_flight.len() as u64;
+        self.window
+            .as_mut()
+            .map(|window| window.summary(now, in_flight))
+    }
+
+    pub fn summary(&self, uptime: Duration) -> SummaryReport {
+        let mut report = SummaryReport::from_samples(
+            &self.samples,
+            self.sent,
+            self.lost,
+            self.late,
+            self.in_flight.len() as u64,
+            uptime,
+        );
+        if let Some(hol) = &self.hol {
+            report.hol_blocked = Some(hol.blocked);
+            report.hol_ms = Some(hol.total.as_secs_f64() * 1000.0);
+        }
+        let paused = self.paused
+            + self
+                .paused_since
+                .map_or(Duration::ZERO, |since| uptime.saturating_sub(since));
+        if !paused.is_zero() {
+            report.paused_s = Some(paused.as_secs_f64());
+        }
+        report
+    }
+}
+
+/// Samples, sends and losses from the last `span` of the session clock.
+/// Samples are placed by arrival, sends and losses by send time.
+#[derive(Debug)]
+struct Window {
+    span: Duration,
+    samples: VecDeque<Sample>,
+    sent: VecDeque<Duration>,
+    lost: VecDeque<Duration>,
+}
+
+impl Window {
+    fn new(span: Duration) -> Self {
+        Window {
+            span,
+            samples: VecDeque::new(),
+            sent: VecDeque::new(),
+            lost: VecDeque::new(),
+        }
+    }
+
+    fn on_sent(&mut self, at: Duration) {
+        self.sent.push_back(at);
+        self.evict(at);
+    }
+
+    fn on_sample(&mut self, sample: Sample) {
+        self.samples.push_back(sample);
+    }
+
+    fn on_lost(&mut self, sent: impl Iterator<Item = Duration>) {
+        self.lost.extend(sent);
+    }
+
+    fn evict(&mut self, now: Duration) {
+        let horizon = now.saturating_sub(self.span);
+        while self
+            .samples
+            .front()
+            .is_some_and(|s| s.at_s < horizon.as_secs_f64())
+        {
+            self.samples.pop_front();
+        }
+        while self.sent.front().is_some_and(|at| *at < horizon) {
+            self.sent.pop_front();
+        }
+        while self.lost.front().is_some_and(|at| *at < horizon) {
+            self.lost.pop_front();
+        }
+    }
+
+    fn summary(&mut self, now: Duration, in_flight: u64) -> SummaryReport {
+        self.evict(now);
+        let samples: Vec<Sample> = self.samples.iter().copied().collect();
+        SummaryReport::from_samples(
+            &samples,
+            self.sent.len() as u64,
+            self.lost.len() as u64,
+            0,
+            in_flight,
+            now.min(self.span),
+        )
+    }
+}
+
+/// A run of consecutive pings counted as lost.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Gap {
+    pub first: u64,
+    pub last: u64,
+    /// Seconds since the session clock started, when `first` was sent
+    pub at_s: f64,
+}
+
+impl fmt::Display for Gap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "lost seq {} at t={:.1}s", self.first, self.at_s)
+        } else {
+            write!(
+                f,
+                "lost seq {}-{} at t={:.1}s",
+                self.first, self.last, self.at_s
+            )
+        }
+    }
+}
+
+/// Merges pings written off one at a time into contiguous gaps.
+#[derive(Debug, Default)]
+struct GapLog {
+    open: Option<Gap>,
+    closed: Vec<Gap>,
+}
+
+impl GapLog {
+    fn on_lost(&mut self, seq: u64, sent: Duration) {
+        match &mut self.open {
+            Some(gap) if gap.last + 1 == seq => gap.last = seq,
+            open => {
+                self.closed.extend(open.take());
+                *open = Some(Gap {
+                    first: seq,
+                    last: seq,
+                    at_s: sent.as_secs_f64(),
+                });
+            }
+        }
+    }
+
+    /// Every seq below `cutoff` is answered or lost, so a gap ending before
+    /// the last of them can no longer grow.
+    fn close_before(&mut self, cutoff: u64) {
+        if self.open.is_some_and(|gap| gap.last + 1 < cutoff) {
+            self.closed.extend(self.open.take());
+        }
+    }
+}
+
+/// Estimates how much of the observed latency an ordered channel spent
+/// waiting behind earlier messages.
+///
+/// The application only sees delivery times. A message delivered right
+/// after its predecessor (within [`HOL_BURST_GAP`]) while that predecessor
+/// was released later than this message could first have arrived (send
+/// time + minimum RTT) is counted as blocked, and the time between that
+/// earliest arrival and its delivery is attributed to head-of-line blocking.
+/// This is an upper bound: part of it may be ordinary queueing delay.
+#[derive(Debug, Default)]
+pub struct HolEstimator {
+    min_rtt: Option<Duration>,
+    prev_delivery: Option<Duration>,
+    blocked: u64,
+    total: Duration,
+}
+
+impl HolEstimator {
+    pub fn on_delivery(&mut self, sent: Duration, delivered: Duration) {
+        let rtt = delivered.saturating_sub(sent);
+        let min_rtt = self.min_rtt.map_or(rtt, |m| m.min(rtt));
+        self.min_rtt = Some(min_rtt);
+
+        let earliest = sent + min_rtt;
+        if let Some(prev) = self.prev_delivery {
+            if prev > earliest && delivered.saturating_sub(prev) <= HOL_BURST_GAP {
+                self.blocked += 1;
+                self.total += delivered - earliest;
+            }
+        }
+        self.prev_delivery = Some(delivered);
+    }
+}
+
+/// Aggregate statistics over a run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SummaryReport {
+    pub sent: u64,
+    pub received: u64,
+    pub lost: u64,
+    /// Echoes that arrived after their ping was counted lost, or twice
+    pub late: u64,
+    /// Pings neither answered nor yet counted lost
+    pub in_flight: u64,
+    pub loss_pct: f64,
+    pub last_ms: Option<f64>,
+    pub min_ms: Option<f64>,
+    pub mean_ms: Option<f64>,
+    /// 95% confidence interval on the mean
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mean_ci_ms: Option<Interval>,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    /// Mean absolute difference between consecutive RTTs
+    pub jitter_ms: Option<f64>,
+    /// Samples estimated to have waited behind an earlier message
+    /// (ordered channels only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hol_blocked: Option<u64>,
+    /// Estimated total head-of-line blocking delay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hol_ms: Option<f64>,
+    /// Time the ping generator spent paused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paused_s: Option<f64>,
+    /// Where the --rtt-target controller settled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_target: Option<RateReport>,
+    /// Forward/return split of the RTT (--one-way)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub one_way: Option<OneWayReport>,
+    /// Percentile confidence intervals (--bootstrap)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bootstrap: Option<BootstrapReport>,
+    pub uptime_s: f64,
+}
+
+impl SummaryReport {
+    pub fn from_samples(
+        samples: &[Sample],
+        sent: u64,
+        lost: u64,
+        late: u64,
+        in_flight: u64,
+        uptime: Duration,
+    ) -> Self {
+        let mut rtts: Vec<f64> = samples.iter().map(|s| s.rtt_ms).collect();
+        let jitter = if rtts.len() > 1 {
+            let total: f64 = rtts.windows(2).map(|w| (w[1] - w[0]).abs()).sum();
+            Some(total / (rtts.len() - 1) as f64)
+        } else {
+            None
+        };
+        let last = rtts.last().copied();
+        rtts.sort_by(|a, b| a.total_cmp(b));
+
+        let answered = samples.len() as u64 + lost;
+        SummaryReport {
+            sent,
+            received: samples.len() as u64,
+            lost,
+            late,
+            in_flight,
+            loss_pct: if answered == 0 {
+                0.0
+            } else {
+                lost as f64 * 100.0 / answered as f64
+            },
+            last_ms: last,
+            min_ms: rtts.first().copied(),
+            mean_ms: mean(&rtts),
+            mean_ci_ms: ci::mean_ci(&rtts),
+            p50_ms: percentile(&rtts, 50.0),
+            p95_ms: percentile(&rtts, 95.0),
+            p99_ms: percentile(&rtts, 99.0),
+            max_ms: rtts.last().copied(),
+            jitter_ms: jitter,
+            hol_blocked: None,
+            hol_ms: None,
+            paused_s: None,
+            rtt_target: None,
+            one_way: None,
+            bootstrap: None,
+            uptime_s: uptime.as_secs_f64(),
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+/// Nearest-rank percentile of already sorted values.
+pub fn percentile(sorted: &[f64], pct: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// Format an optional millisecond value for text output.
+pub fn ms(value: Option<f64>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| format!("{:.2}", v))
+}
+
+impl fmt::Display for SummaryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} sent, {} received, {} lost ({:.1}%), {} late, {} in flight, {:.1}s",
+            self.sent,
+            self.received,
+            self.lost,
+            self.loss_pct,
+            self.late,
+            self.in_flight,
+            self.uptime_s
+        )?;
+        if let Some(paused) = self.paused_s {
+            write!(f, " ({:.1}s paused)", paused)?;
+        }
+        writeln!(f)?;
+        write!(
+            f,
+            "rtt min/mean/p50/p95/p99/max = {}/{}/{}/{}/{}/{} ms, jitter {} ms",
+            ms(self.min_ms),
+            ms(self.mean_ms),
+            ms(self.p50_ms),
+            ms(self.p95_ms),
+            ms(self.p99_ms),
+            ms(self.max_ms),
+            ms(self.jitter_ms)
+        )?;
+        if let Some(interval) = self.mean_ci_ms {
+            write!(f, "\nmean 95% CI = {} ms", interval)?;
+        }
+        if let Some(bootstrap) = &self.bootstrap {
+            write!(f, "\n{}", bootstrap)?;
+        }
+        if let (Some(blocked), Some(total)) = (self.hol_blocked, self.hol_ms) {
+            write!(
+                f,
+                "\nhead-of-line blocking: {} samples, ~{:.2} ms total",
+                blocked, total
+            )?;
+        }
+        if let Some(rate) = &self.rtt_target {
+            write!(f, "\n{}", rate)?;
+        }
+        if let Some(split) = &self.one_way {
+            write!(f, "\n{}", split)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::loss::SeededDrop;
+
+    fn at(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn lost_seqs(gaps: &[Gap]) -> Vec<u64> {
+        gaps.iter().flat_map(|g| g.first..=g.last).collect()
+    }
+
+    #[test]
+    fn bounded_run_loses_exactly_the_seeded_drops() {
+        for seed in 0..8 {
+            let drop = SeededDrop::new(0.2, seed);
+            let mut stats = Stats::new().with_gap_log();
+            let mut gaps = Vec::new();
+            for seq in 0..40 {
+                stats.on_sent(seq, at(seq * 1000));
+                if !drop.drops(seq) {
+                    stats.on_echo(seq, at(seq * 1000), at(seq * 1000 + 5));
+                }
+                gaps.extend(stats.take_gaps());
+            }
+            stats.finish();
+            gaps.extend(stats.finish_gaps());
+
+            let dropped: Vec<u64> = (0..40).filter(|&seq| drop.drops(seq)).collect();
+            assert_eq!(lost_seqs(&gaps), dropped, "seed {}", seed);
+            let report = stats.summary(at(40_000));
+            assert_eq!(report.lost, dropped.len() as u64);
+            assert_eq!(report.in_flight, 0);
+        }
+    }
+
+    /// Deliveries of pings sent every 20 ms over a 10 ms path, where the
+    /// first transmission of `delayed` is lost and its retransmission
+    /// lands 100 ms late. An ordered channel holds the pings behind it.
+    fn ordered_deliveries(delayed: Option<u64>) -> Vec<(u64, Duration, Duration)> {
+        let mut released = Duration::ZERO;
+        (0..10)
+            .map(|seq| {
+                let sent = at(seq * 20);
+                let extra = if Some(seq) == delayed { 100 } else { 0 };
+                released = released.max(sent + at(10 + extra));
+                (seq, sent, released)
+            })
+            .collect()
+    }
+
+    fn hol_report(deliveries: &[(u64, Duration, Duration)]) -> SummaryReport {
+        let mut stats = Stats::new().with_hol_estimate();
+        for &(seq, sent, delivered) in deliveries {
+            stats.on_sent(seq, sent);
+            stats.on_echo(seq, sent, delivered);
+        }
+        stats.summary(at(1000))
+    }
+
+    #[test]
+    fn held_back_pings_are_counted_as_blocked() {
+        let report = hol_report(&ordered_deliveries(Some(3)));
+        // Pings 4 to 7 could have arrived at 90, 110, 130 and 150 ms but
+        // were all released with ping 3 at 170 ms
+        assert_eq!(report.hol_blocked, Some(4));
+        let hol_ms = report.hol_ms.unwrap();
+        assert!((hol_ms - 200.0).abs() < 1e-6, "{}", hol_ms);
+
+        let report = hol_report(&ordered_deliveries(None));
+        assert_eq!(report.hol_blocked, Some(0));
+        assert_eq!(report.hol_ms, Some(0.0));
+        assert_eq!(Stats::new().summary(at(1000)).hol_blocked, None);
+    }
+
+    #[test]
+    fn contiguous_losses_make_one_gap() {
+        let mut stats = Stats::new().with_gap_log();
+        let mut gaps = Vec::new();
+        for seq in 0..60 {
+            stats.on_sent(seq, at(seq * 100));
+            if !(42..=44).contains(&seq) && seq != 50 {
+                stats.on_echo(seq, at(seq * 100), at(seq * 100 + 5));
+            }
+            gaps.extend(stats.take_gaps());
+        }
+        // Both gaps are reported while the run goes on, not at its end
+        stats.finish();
+        assert_eq!(stats.finish_gaps(), []);
+
+        assert_eq!(
+            gaps,
+            [
+                Gap {
+                    first: 42,
+                    last: 44,
+                    at_s: 4.2
+                },
+                Gap {
+                    first: 50,
+                    last: 50,
+                    at_s: 5.0
+                },
+            ]
+        );
+        assert_eq!(gaps[0].to_string(), "lost seq 42-44 at t=4.2s");
+        assert_eq!(gaps[1].to_string(), "lost seq 50 at t=5.0s");
+        assert_eq!(stats.summary(at(6000)).lost, 4);
+    }
+
+    #[test]
+    fn stale_pings_are_lost_once_and_their_echoes_late() {
+        let mut stats = Stats::new().with_match_timeout(at(2000)).with_gap_log();
+        stats.on_sent(0, at(0));
+        stats.on_sent(1, at(1000));
+        stats.on_echo(1, at(1000), at(1010));
+        stats.on_sent(2, at(2000));
+        assert_eq!(stats.summary(at(2000)).lost, 0);
+        // seq 0 has now waited longer than the timeout
+        stats.on_sent(3, at(3000));
+        let report = stats.summary(at(3000));
+        assert_eq!((report.lost, report.in_flight), (1, 2));
+
+        // Its echo turns up after all, and the window rule passes it too
+        assert!(stats.on_echo(0, at(0), at(3500)).is_none());
+        for seq in 4..20 {
+            stats.on_sent(seq, at(3000 + seq * 10));
+            stats.on_echo(seq, at(3000 + seq * 10), at(3005 + seq * 10));
+        }
+        let report = stats.summary(at(4000));
+        assert_eq!(report.late, 1);
+        // seq 2 and 3 were never echoed and expired through the window
+        assert_eq!(report.lost, 3);
+        assert_eq!(report.received, 17);
+        stats.finish();
+        let mut gaps = stats.take_gaps();
+        gaps.extend(stats.finish_gaps());
+        assert_eq!(lost_seqs(&gaps), [0, 2, 3]);
+    }
+
+    #[test]
+    fn a_pause_adds_no_loss() {
+        let mut stats = Stats::new().with_match_timeout(at(2000));
+        let ping = |stats: &mut Stats, seq: u64, ms: u64| {
+            stats.on_sent(seq, at(ms));
+            stats.on_echo(seq, at(ms), at(ms + 20));
+        };
+        for seq in 0..5 {
+            ping(&mut stats, seq, seq * 1000);
+        }
+        stats.on_pause(at(4500));
+        // Still paused: the pause so far is reported
+        assert_eq!(stats.summary(at(6500)).paused_s, Some(2.0));
+        stats.on_resume(at(14_500));
+        for seq in 5..10 {
+            ping(&mut stats, seq, 14_500 + (seq - 5) * 1000);
+        }
+        let report = stats.summary(at(19_000));
+        assert_eq!((report.sent, report.received, report.lost), (10, 10, 0));
+        assert_eq!(report.paused_s, Some(10.0));
+        assert_eq!(Stats::new().summary(at(1000)).paused_s, None);
+    }
+
+    #[test]
+    fn the_window_forgets_what_the_summary_keeps() {
+        let mut stats = Stats::new().with_window(at(10_000));
+        for seq in 0..30 {
+            let sent = at(seq * 1000);
+            stats.on_sent(seq, sent);
+            let rtt = if seq < 10 { 100 } else { 10 };
+            if seq != 5 {
+                stats.on_echo(seq, sent, sent + at(rtt));
+            }
+        }
+        let now = at(29_500);
+        let window = stats.window_summary(now).unwrap();
+        // Sends from 19.5 s on, echoes arriving from then on
+        assert_eq!((window.sent, window.received, window.lost), (10, 10, 0));
+        assert_eq!(window.max_ms, Some(10.0));
+
+        let whole = stats.summary(now);
+        assert_eq!((whole.sent, whole.received, whole.lost), (30, 29, 1));
+        assert_eq!(whole.max_ms, Some(100.0));
+        assert!(Stats::new().window_summary(now).is_none());
+    }
+}
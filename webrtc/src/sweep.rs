@@ -0,0 +1,213 @@
+//! Payload size sweeps: one bounded probe per size, summarized in a table.
+
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::stats::{ms, Sample, SummaryReport};
+use crate::wire;
+
+/// Payload sizes from `start` to `end` inclusive, `step` bytes apart,
+/// written `16..1200 step 128`. Steps below the ping header are probed
+/// once, at the header's size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadSweep {
+    pub start: usize,
+    pub end: usize,
+    pub step: usize,
+}
+
+impl PayloadSweep {
+    /// Default spacing when the spec names no step.
+    pub const DEFAULT_STEP: usize = 128;
+
+    pub fn largest(&self) -> usize {
+        self.sizes().last().unwrap_or(self.start)
+    }
+
+    pub fn sizes(&self) -> impl Iterator<Item = usize> {
+        // Raising sizes keeps them in order, so repeats are adjacent
+        let mut last = None;
+        (self.start..=self.end)
+            .step_by(self.step)
+            .map(|size| size.max(wire::PING_HEADER_LEN))
+            .filter(move |&size| last.replace(size) != Some(size))
+    }
+}
+
+impl FromStr for PayloadSweep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, step) = match s.split_once("step") {
+            Some((range, step)) => (range, Some(step)),
+            None => (s, None),
+        };
+        let (start, end) = range
+            .trim()
+            .split_once("..")
+            .ok_or_else(|| format!("expected START..END [step N], got {:?}", s))?;
+        let size = |v: &str| {
+            v.trim()
+                .parse::<usize>()
+                .map_err(|e| format!("invalid size {:?}: {}", v.trim(), e))
+        };
+        let sweep = PayloadSweep {
+            start: size(start)?,
+            end: size(end)?,
+            step: step.map(size).transpose()?.unwrap_or(Self::DEFAULT_STEP),
+        };
+        if sweep.end < sweep.start || sweep.step == 0 {
+            return Err(format!("empty sweep {:?}", s));
+        }
+        if sweep.end < wire::PING_HEADER_LEN {
+            return Err(format!(
+                "pings need at least {} bytes, sweep ends at {}",
+                wire::PING_HEADER_LEN,
+                sweep.end
+            ));
+        }
+        Ok(sweep)
+    }
+}
+
+impl fmt::Display for PayloadSweep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{} step {}", self.start, self.end, self.step)
+    }
+}
+
+impl Serialize for PayloadSweep {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+pub const CSV_HEADER: &str = "payload,sent,received,mean_ms,p95_ms,throughput_kbps";
+
+/// Result of the probe at one payload size.
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepRow {
+    pub payload: usize,
+    pub sent: u64,
+    pub received: u64,
+    pub mean_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    /// Rate a sender waiting for each echo would reach: payload / mean RTT
+    pub throughput_kbps: Option<f64>,
+}
+
+impl SweepRow {
+    /// Summarize the echoes of one step. Pings not echoed by now are simply
+    /// not received; the step does not wait on the run's loss window.
+    pub fn from_samples(payload: usize, sent: u64, samples: &[Sample]) -> Self {
+        let report = SummaryReport::from_samples(samples, sent, 0, 0, 0, Default::default());
+        SweepRow {
+            payload,
+            sent,
+            received: report.received,
+            mean_ms: report.mean_ms,
+            p95_ms: report.p95_ms,
+            throughput_kbps: report
+                .mean_ms
+                .filter(|m| *m > 0.0)
+                .map(|m| payload as f64 * 8.0 / m),
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let opt = |v: Option<f64>| v.map_or_else(String::new, |v| format!("{:.3}", v));
+        format!(
+            "{},{},{},{},{},{}",
+            self.payload,
+            self.sent,
+            self.received,
+            opt(self.mean_ms),
+            opt(self.p95_ms),
+            opt(self.throughput_kbps)
+        )
+    }
+}
+
+/// Render rows as an aligned text table.
+pub fn format_table(rows: &[SweepRow]) -> String {
+    let mut out = format!(
+        "{:>8} {:>9} {:>10} {:>10} {:>12}\n",
+        "payload", "recv", "mean ms", "p95 ms", "kbit/s"
+    );
+    for row in rows {
+        out.push_str(&format!(
+            "{:>8} {:>9} {:>10} {:>10} {:>12}\n",
+            row.payload,
+            format!("{}/{}", row.received, row.sent),
+            ms(row.mean_ms),
+            ms(row.p95_ms),
+            row.throughput_kbps
+                .map_or_else(|| "-".to_string(), |v| format!("{:.1}", v))
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_row_per_step() {
+        let sweep: PayloadSweep = "16..1200 step 128".parse().unwrap();
+        let sizes: Vec<usize> = sweep.sizes().collect();
+        // The first step is raised to the ping header's 17 bytes
+        assert_eq!(sizes[0], wire::PING_HEADER_LEN);
+        assert_eq!(
+            sizes[1..],
+            (1..10).map(|i| 16 + i * 128).collect::<Vec<_>>()
+        );
+        assert_eq!(sweep.largest(), 1168);
+        assert_eq!(sweep.to_string().parse::<PayloadSweep>(), Ok(sweep));
+
+        let rows: Vec<SweepRow> = sweep
+            .sizes()
+            .map(|payload| {
+                let samples: Vec<Sample> = (0..3)
+                    .map(|seq| Sample {
+                        seq,
+                        at_s: seq as f64,
+                        rtt_ms: 2.0,
+                    })
+                    .collect();
+                SweepRow::from_samples(payload, 4, &samples)
+            })
+            .collect();
+        assert_eq!(rows.len(), 10);
+        assert_eq!(rows[1].to_csv(), "144,4,3,2.000,2.000,576.000");
+        let table = format_table(&rows);
+        assert_eq!(table.lines().count(), 11);
+        assert!(table.lines().last().unwrap().contains("1168"));
+        assert!(table
+            .lines()
+            .all(|l| l.len() == table.lines().next().unwrap().len()));
+
+        // Several steps below the header make one probe at its size
+        for (spec, sizes) in [
+            ("8..40 step 4", vec![17, 20, 24, 28, 32, 36, 40]),
+            ("9..40 step 4", vec![17, 21, 25, 29, 33, 37]),
+            ("1..17 step 1", vec![17]),
+        ] {
+            let sweep: PayloadSweep = spec.parse().unwrap();
+            assert_eq!(sweep.sizes().collect::<Vec<_>>(), sizes, "{}", spec);
+            assert_eq!(sweep.largest(), *sizes.last().unwrap(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn bad_specs_are_refused() {
+        assert_eq!(
+            "16..1200".parse::<PayloadSweep>().unwrap().step,
+            PayloadSweep::DEFAULT_STEP
+        );
+        for spec in ["8..16", "1200..16", "16..1200 step 0", "16-1200", "16..x"] {
+            assert!(spec.parse::<PayloadSweep>().is_err(), "{}", spec);
+        }
+    }
+}
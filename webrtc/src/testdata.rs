@@ -0,0 +1,68 @@
+//! SDP shared by the unit tests: an offer as webrtc-rs writes it, with one
+//! IPv4 and one IPv6 host candidate, each listed for both components.
+
+use clap::Parser;
+use std::sync::{Mutex, MutexGuard};
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+use crate::cli::CommonArgs;
+
+pub const OFFER_SDP: &str = "v=0\r
+o=- 8577816601537873069 822725601 IN IP4 0.0.0.0\r
+s=-\r
+t=0 0\r
+a=fingerprint:sha-256 90:10:EC:F2:E0:02:31:7B:E1:AA:7D:A6:94:9A:43:96:9F:76:39:CF:D0:EA:62:B2:FD:80:23:F2:5C:76:E3:D9\r
+a=group:BUNDLE 0\r
+m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r
+c=IN IP4 0.0.0.0\r
+a=setup:actpass\r
+a=mid:0\r
+a=sendrecv\r
+a=sctp-port:5000\r
+a=ice-ufrag:uTvunyxsbINnYhRb\r
+a=ice-pwd:FLvevoeZDlBIOTgqBTKHBqrwwQOzAMtx\r
+a=candidate:607854466 1 udp 2130706431 192.0.2.2 33022 typ host\r
+a=candidate:607854466 2 udp 2130706431 192.0.2.2 33022 typ host\r
+a=candidate:2722046357 1 udp 2130706431 fd00::2 52087 typ host\r
+a=candidate:2722046357 2 udp 2130706431 fd00::2 52087 typ host\r
+a=end-of-candidates\r
+";
+
+/// [`OFFER_SDP`] with `edit` applied, as a parsed offer.
+pub fn offer_with(edit: impl FnOnce(&str) -> String) -> RTCSessionDescription {
+    RTCSessionDescription::offer(edit(OFFER_SDP)).expect("test SDP must parse")
+}
+
+/// [`OFFER_SDP`] as a parsed offer.
+pub fn offer() -> RTCSessionDescription {
+    offer_with(str::to_string)
+}
+
+/// Held by tests that set RC_WEBRTC_* variables, and while parsing, so no
+/// test sees another's environment.
+static ENV: Mutex<()> = Mutex::new(());
+
+pub fn lock_env() -> MutexGuard<'static, ()> {
+    ENV.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Parser)]
+#[command(name = "test")]
+struct Cli {
+    #[command(flatten)]
+    common: CommonArgs,
+}
+
+/// Parse `args` as the shared options, with the caller holding
+/// [`lock_env`].
+pub fn parse_common_locked(args: &[&str]) -> CommonArgs {
+    Cli::try_parse_from(std::iter::once("test").chain(args.iter().copied()))
+        .unwrap()
+        .common
+}
+
+/// Parse `args` as the shared options.
+pub fn common_args(args: &[&str]) -> CommonArgs {
+    let _env = lock_env();
+    parse_common_locked(args)
+}
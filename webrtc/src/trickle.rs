@@ -0,0 +1,170 @@
+//! Candidates exchanged outside the SDP, one compact JSON line each, for
+//! peers that expect pure trickle ICE.
+
+use anyhow::{Context, Result};
+use std::io;
+use std::sync::Weak;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::cidr::AddressFilter;
+use crate::sdp;
+
+/// Queue local candidates as ICE gathers them. Register before setting the
+/// local description; the queue closes when gathering completes.
+pub fn collect(pc: &RTCPeerConnection) -> mpsc::UnboundedReceiver<RTCIceCandidateInit> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut tx = Some(tx);
+    pc.on_ice_candidate(Box::new(move |c: Option<RTCIceCandidate>| {
+        match c {
+            Some(c) => match (c.to_json(), &tx) {
+                (Ok(init), Some(tx)) => {
+                    let _ = tx.send(init);
+                }
+                (Err(e), _) => eprintln!("warning: cannot encode local candidate: {}", e),
+                _ => {}
+            },
+            None => tx = None,
+        }
+        Box::pin(async {})
+    }));
+    rx
+}
+
+/// Remove the candidates from a local description, which must still parse.
+pub fn strip_candidates(desc: &mut RTCSessionDescription) -> Result<()> {
+    desc.sdp = sdp::strip_candidates(&desc.sdp);
+    desc.unmarshal()
+        .context("SDP no longer parses without its candidates")?;
+    Ok(())
+}
+
+/// Encode a candidate as the line the other peer pastes.
+pub fn encode(init: &RTCIceCandidateInit) -> Result<String> {
+    Ok(serde_json::to_string(init)?)
+}
+
+/// Decode a pasted candidate line.
+pub fn decode(line: &str) -> Result<RTCIceCandidateInit> {
+    serde_json::from_str(line.trim()).context("line is not a JSON candidate")
+}
+
+/// Print queued candidates once the description they belong to is out;
+/// `trickle_only` says whether that description left them out. webrtc-rs
+/// leaves sdpMid empty, so it is filled in from the description.
+pub async fn print_local(
+    mut rx: mpsc::UnboundedReceiver<RTCIceCandidateInit>,
+    mid: Option<String>,
+    trickle_only: bool,
+    cancel: CancellationToken,
+) {
+    let mut first = true;
+    while let Some(Some(mut init)) = cancel.run_until_cancelled(rx.recv()).await {
+        if first {
+            if trickle_only {
+                println!("\n=== Send these CANDIDATES to the other peer, one per line ===\n");
+            } else {
+                println!("\n=== Gathered candidates (also in the SDP above) ===\n");
+            }
+            first = false;
+        }
+        if init.sdp_mid.as_deref().is_none_or(str::is_empty) {
+            init.sdp_mid = mid.clone();
+        }
+        match encode(&init) {
+            Ok(line) => println!("{}", line),
+            Err(e) => eprintln!("warning: cannot encode local candidate: {}", e),
+        }
+    }
+}
+
+/// Add candidates pasted one per line, for a remote whose description
+/// carried none. Candidates the address filter refuses are dropped.
+pub async fn add_remote(
+    pc: Weak<RTCPeerConnection>,
+    filter: AddressFilter,
+    mut lines: mpsc::Receiver<io::Result<String>>,
+    cancel: CancellationToken,
+) {
+    while let Some(Some(Ok(line))) = cancel.run_until_cancelled(lines.recv()).await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let init = match decode(&line) {
+            Ok(init) => init,
+            Err(e) => {
+                eprintln!("warning: ignoring pasted line: {:#}", e);
+                continue;
+            }
+        };
+        if let Some(c) = sdp::parse_candidate(&init.candidate) {
+            if !filter.permits(&c.address) {
+                println!(
+                    "Dropping remote candidate {}:{} ({})",
+                    c.address, c.port, c.typ
+                );
+                continue;
+            }
+        }
+        let Some(pc) = pc.upgrade() else {
+            break;
+        };
+        if let Err(e) = pc.add_ice_candidate(init).await {
+            eprintln!("warning: cannot add remote candidate: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::api::APIBuilder;
+
+    use crate::testdata;
+
+    fn init() -> RTCIceCandidateInit {
+        RTCIceCandidateInit {
+            candidate: "candidate:607854466 1 udp 2130706431 192.0.2.2 33022 typ host".to_string(),
+            sdp_mid: Some("0".to_string()),
+            sdp_mline_index: Some(0),
+            username_fragment: Some("uTvunyxsbINnYhRb".to_string()),
+        }
+    }
+
+    #[test]
+    fn candidates_round_trip() {
+        let line = encode(&init()).unwrap();
+        assert!(!line.contains('\n'));
+        assert!(line.contains(r#""sdpMid":"0""#), "{}", line);
+        assert!(line.contains(r#""sdpMLineIndex":0"#), "{}", line);
+        assert_eq!(decode(&format!("  {}\r\n", line)).unwrap(), init());
+
+        // As a browser prints it, with unset fields null
+        let browser = r#"{"candidate":"candidate:1 1 udp 1 192.0.2.9 9 typ host","sdpMid":null,"sdpMLineIndex":null,"usernameFragment":null}"#;
+        let decoded = decode(browser).unwrap();
+        assert_eq!(decoded.sdp_mid, None);
+        assert_eq!(
+            decoded.candidate,
+            "candidate:1 1 udp 1 192.0.2.9 9 typ host"
+        );
+        assert!(decode("a=candidate:1 1 udp 1 192.0.2.9 9 typ host").is_err());
+    }
+
+    #[tokio::test]
+    async fn decoded_candidates_are_accepted() {
+        let pc = APIBuilder::new()
+            .build()
+            .new_peer_connection(Default::default())
+            .await
+            .unwrap();
+        let mut offer = testdata::offer();
+        strip_candidates(&mut offer).unwrap();
+        pc.set_remote_description(offer).await.unwrap();
+        let line = encode(&init()).unwrap();
+        pc.add_ice_candidate(decode(&line).unwrap()).await.unwrap();
+        pc.close().await.unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the framed wire format spoken by this build.
+pub const FORMAT_VERSION: u16 = 2;
+
+/// Pings carry a sequence number.
+pub const FEATURE_SEQ: u32 = 1 << 0;
+/// Every frame starts with a type tag.
+pub const FEATURE_TYPE_TAG: u32 = 1 << 1;
+/// Stamped pings are echoed with the answer's receive and reply times.
+/// Optional: peers without it are still compatible.
+pub const FEATURE_STAMP: u32 = 1 << 2;
+/// Keepalive pings are answered with pongs. Optional.
+pub const FEATURE_KEEPALIVE: u32 = 1 << 3;
+
+/// Features a peer must have for stats against it to be trusted.
+const REQUIRED_FEATURES: u32 = FEATURE_SEQ | FEATURE_TYPE_TAG;
+
+const TAG_HELLO: u8 = 0x01;
+const TAG_PING: u8 = 0x02;
+const TAG_STAMPED: u8 = 0x03;
+const TAG_KEEPALIVE_PING: u8 = 0x04;
+const TAG_KEEPALIVE_PONG: u8 = 0x05;
+
+/// Size of a ping without padding.
+pub const PING_HEADER_LEN: usize = 17;
+
+/// Size of a stamped ping without padding.
+pub const STAMPED_HEADER_LEN: usize = 33;
+
+/// Length of the untagged timestamp pings sent by older builds.
+pub const LEGACY_PING_LEN: usize = 16;
+
+/// Sequence number of the connectivity probe; measurement pings never
+/// reach it.
+pub const PROBE_SEQ: u64 = u64::MAX;
+
+/// Capability descriptor each peer sends when the channel opens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatDescriptor {
+    pub version: u16,
+    pub features: u32,
+}
+
+impl FormatDescriptor {
+    /// Descriptor for the format implemented by this build.
+    pub fn current() -> Self {
+        FormatDescriptor {
+            version: FORMAT_VERSION,
+            features: FEATURE_SEQ | FEATURE_TYPE_TAG | FEATURE_STAMP | FEATURE_KEEPALIVE,
+        }
+    }
+
+    /// Whether stats computed against `peer` can be trusted.
+    pub fn is_compatible(&self, peer: &FormatDescriptor) -> bool {
+        self.version == peer.version && peer.features & REQUIRED_FEATURES == REQUIRED_FEATURES
+    }
+
+    pub fn has(&self, feature: u32) -> bool {
+        self.features & feature == feature
+    }
+}
+
+/// A message on the latency channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Hello(FormatDescriptor),
+    /// Echoed back unchanged by the answer. `padding` zero bytes follow
+    /// the header to reach the configured payload size.
+    Ping {
+        seq: u64,
+        sent_ns: u64,
+        padding: usize,
+    },
+    /// A ping the answer echoes with `recv_ns` and `reply_ns` filled in
+    /// from its wall clock (ns since the Unix epoch); both zero as sent.
+    Stamped {
+        seq: u64,
+        sent_ns: u64,
+        recv_ns: u64,
+        reply_ns: u64,
+        padding: usize,
+    },
+    /// Liveness check, answered with a pong carrying the same nonce. Never
+    /// counted in the latency stats.
+    KeepalivePing {
+        nonce: u64,
+    },
+    KeepalivePong {
+        nonce: u64,
+    },
+    /// Untagged 16-byte timestamp from builds predating the framed format.
+    Legacy {
+        sent_ns: u128,
+    },
+}
+
+impl Frame {
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        match self {
+            Frame::Hello(desc) => {
+                buf.put_u8(TAG_HELLO);
+                buf.put_u16(desc.version);
+                buf.put_u32(desc.features);
+            }
+            Frame::Ping {
+                seq,
+                sent_ns,
+                padding,
+            } => {
+                buf.put_u8(TAG_PING);
+                buf.put_u64(*seq);
+                buf.put_u64(*sent_ns);
+                buf.put_bytes(0, *padding);
+            }
+            Frame::Stamped {
+                seq,
+                sent_ns,
+                recv_ns,
+                reply_ns,
+                padding,
+            } => {
+                buf.put_u8(TAG_STAMPED);
+                buf.put_u64(*seq);
+                buf.put_u64(*sent_ns);
+                buf.put_u64(*recv_ns);
+                buf.put_u64(*reply_ns);
+                buf.put_bytes(0, *padding);
+            }
+            Frame::KeepalivePing { nonce } => {
+                buf.put_u8(TAG_KEEPALIVE_PING);
+                buf.put_u64(*nonce);
+            }
+            Frame::KeepalivePong { nonce } => {
+                buf.put_u8(TAG_KEEPALIVE_PONG);
+                buf.put_u64(*nonce);
+            }
+            Frame::Legacy { sent_ns } => buf.put_u128_le(*sent_ns),
+        }
+        buf.freeze()
+    }
+
+    /// Parse a message, returning `None` for anything that is not a frame
+    /// (e.g. the answer's text greetings).
+    pub fn decode(data: &[u8]) -> Option<Frame> {
+        let mut buf = data;
+        match (buf.first().copied(), buf.len()) {
+            (Some(TAG_HELLO), 7) => {
+                buf.advance(1);
+                Some(Frame::Hello(FormatDescriptor {
+                    version: buf.get_u16(),
+                    features: buf.get_u32(),
+                }))
+            }
+            (Some(TAG_PING), len) if len >= PING_HEADER_LEN => {
+                buf.advance(1);
+                Some(Frame::Ping {
+                    seq: buf.get_u64(),
+                    sent_ns: buf.get_u64(),
+                    padding: len - PING_HEADER_LEN,
+                })
+            }
+            (Some(TAG_STAMPED), len) if len >= STAMPED_HEADER_LEN => {
+                buf.advance(1);
+                Some(Frame::Stamped {
+                    seq: buf.get_u64(),
+                    sent_ns: buf.get_u64(),
+                    recv_ns: buf.get_u64(),
+                    reply_ns: buf.get_u64(),
+                    padding: len - STAMPED_HEADER_LEN,
+                })
+            }
+            (Some(TAG_KEEPALIVE_PING), 9) => {
+                buf.advance(1);
+                Some(Frame::KeepalivePing {
+                    nonce: buf.get_u64(),
+                })
+            }
+            (Some(TAG_KEEPALIVE_PONG), 9) => {
+                buf.advance(1);
+                Some(Frame::KeepalivePong {
+                    nonce: buf.get_u64(),
+                })
+            }
+            (_, LEGACY_PING_LEN) => Some(Frame::Legacy {
+                sent_ns: buf.get_u128_le(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Describe why `peer` (or a peer that sent no descriptor) is incompatible
+/// with this build, if it is.
+pub fn check_peer_format(peer: Option<&FormatDescriptor>) -> Result<(), String> {
+    let ours = FormatDescriptor::current();
+    match peer {
+        Some(peer) if ours.is_compatible(peer) => Ok(()),
+        Some(peer) => Err(format!(
+            "peer speaks wire format v{} (features {:#x}), this build speaks v{} (features {:#x})",
+            peer.version, peer.features, ours.version, ours.features
+        )),
+        None => Err("peer sent no format descriptor, it is probably an older build".to_string()),
+    }
+}
+
+/// Wall-clock time for stamped pings, in ns since the Unix epoch.
+pub fn wall_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_frame_round_trips() {
+        let frames = [
+            Frame::Hello(FormatDescriptor::current()),
+            Frame::Ping {
+                seq: 7,
+                sent_ns: 123_456_789,
+                padding: 0,
+            },
+            Frame::Ping {
+                seq: PROBE_SEQ,
+                sent_ns: 0,
+                padding: 100,
+            },
+            Frame::Stamped {
+                seq: 9,
+                sent_ns: 1,
+                recv_ns: 2,
+                reply_ns: 3,
+                padding: 5,
+            },
+            Frame::KeepalivePing { nonce: 42 },
+            Frame::KeepalivePong { nonce: u64::MAX },
+            Frame::Legacy {
+                sent_ns: 987_654_321_012,
+            },
+        ];
+        for frame in frames {
+            let bytes = frame.encode();
+            assert_eq!(Frame::decode(&bytes), Some(frame.clone()), "{:?}", frame);
+        }
+        assert_eq!(
+            Frame::Ping {
+                seq: 0,
+                sent_ns: 0,
+                padding: 3
+            }
+            .encode()
+            .len(),
+            PING_HEADER_LEN + 3
+        );
+    }
+
+    #[test]
+    fn non_frames_are_not_decoded() {
+        for data in [
+            &b""[..],
+            b"Hello at Instant { tv_sec: 1 }",
+            &[TAG_HELLO, 0, 2],
+            &[TAG_PING; 10],
+            &[0x7f; 20],
+        ] {
+            assert_eq!(Frame::decode(data), None, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn compatibility_needs_version_and_required_features() {
+        let ours = FormatDescriptor::current();
+        assert!(check_peer_format(Some(&ours)).is_ok());
+        let newer = FormatDescriptor {
+            version: ours.version + 1,
+            ..ours
+        };
+        let why = check_peer_format(Some(&newer)).unwrap_err();
+        assert!(why.contains(&format!("v{}", ours.version + 1)), "{}", why);
+        let untagged = FormatDescriptor {
+            features: FEATURE_SEQ,
+            ..ours
+        };
+        assert!(check_peer_format(Some(&untagged)).is_err());
+        assert!(check_peer_format(None).unwrap_err().contains("older build"));
+    }
+}
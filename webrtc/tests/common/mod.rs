@@ -0,0 +1,230 @@
+//! Runs the offer and answer binaries against each other on this host,
+//! relaying the pasted blobs the way a user would.
+#![allow(dead_code)]
+
+pub mod sink;
+
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// An unreachable STUN server, so the tests never leave the host.
+pub const NO_STUN: &str = "stun:127.0.0.1:1";
+
+/// How long a peer may take to print its blob.
+const BLOB_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub fn offer_bin() -> &'static str {
+    env!("CARGO_BIN_EXE_offer")
+}
+
+pub fn answer_bin() -> &'static str {
+    env!("CARGO_BIN_EXE_answer")
+}
+
+/// A running binary. Its stdout and stderr are read as one stream, so
+/// the order of lines across the two is kept.
+pub struct Peer {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    lines: Receiver<String>,
+    output: Arc<Mutex<Vec<String>>>,
+}
+
+/// A peer that has exited, or was stopped.
+pub struct Finished {
+    pub status: ExitStatus,
+    pub output: Vec<String>,
+}
+
+impl Peer {
+    /// Start `bin` with `args`, plus `--stun` pointing nowhere.
+    pub fn spawn(bin: &str, args: &[&str]) -> Peer {
+        Peer::spawn_with_env(bin, args, &[])
+    }
+
+    /// [`Peer::spawn`] with extra environment variables, such as RUST_LOG.
+    pub fn spawn_with_env(bin: &str, args: &[&str], env: &[(&str, &str)]) -> Peer {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("exec \"$0\" \"$@\" 2>&1")
+            .arg(bin)
+            .args(["--stun", NO_STUN])
+            .args(args)
+            .env("RUST_BACKTRACE", "0")
+            .envs(env.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| panic!("cannot start {}: {}", bin, e));
+        let stdout = child.stdout.take().unwrap();
+        let (tx, lines) = mpsc::channel();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let output2 = Arc::clone(&output);
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                output2.lock().unwrap().push(line.clone());
+                let _ = tx.send(line);
+            }
+        });
+        Peer {
+            stdin: child.stdin.take(),
+            child,
+            lines,
+            output,
+        }
+    }
+
+    /// Wait for the next line matching `want`.
+    pub fn expect(&self, want: impl Fn(&str) -> bool, limit: Duration) -> String {
+        let deadline = Instant::now() + limit;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(left) {
+                Ok(line) if want(&line) => return line,
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    panic!("timed out; output so far:\n{}", self.output().join("\n"))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    panic!("peer exited; output:\n{}", self.output().join("\n"))
+                }
+            }
+        }
+    }
+
+    /// The next offer or answer blob the peer prints.
+    pub fn blob(&self) -> String {
+        self.expect(is_blob, BLOB_TIMEOUT)
+    }
+
+    pub fn send_line(&mut self, line: &str) {
+        let stdin = self.stdin.as_mut().expect("stdin already closed");
+        writeln!(stdin, "{}", line).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    pub fn output(&self) -> Vec<String> {
+        self.output.lock().unwrap().clone()
+    }
+
+    /// The trickled candidate lines printed so far, ready to paste into
+    /// the other peer.
+    pub fn candidates(&self) -> Vec<String> {
+        self.output()
+            .into_iter()
+            .filter(|line| {
+                serde_json::from_str::<Value>(line)
+                    .is_ok_and(|v| v.get("candidate").is_some() && v.get("event").is_none())
+            })
+            .collect()
+    }
+
+    /// Wait up to `limit` for the peer to exit on its own, then stop it.
+    pub fn wait(mut self, limit: Duration) -> Finished {
+        let deadline = Instant::now() + limit;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return self.finished(status);
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        self.stop()
+    }
+
+    /// Interrupt the peer as Ctrl-C would, killing it if it lingers.
+    pub fn stop(mut self) -> Finished {
+        let _ = Command::new("kill")
+            .args(["-INT", &self.child.id().to_string()])
+            .status();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return self.finished(status);
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        let _ = self.child.kill();
+        let status = self.child.wait().unwrap();
+        self.finished(status)
+    }
+
+    fn finished(self, status: ExitStatus) -> Finished {
+        // Let the reader thread drain what is left of the pipe
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if let Err(RecvTimeoutError::Disconnected) =
+                self.lines.recv_timeout(Duration::from_millis(50))
+            {
+                break;
+            }
+        }
+        Finished {
+            status,
+            output: self.output(),
+        }
+    }
+}
+
+impl Finished {
+    /// Lines that parse as JSON objects, such as --json events.
+    pub fn json(&self) -> Vec<Value> {
+        self.output
+            .iter()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .filter(Value::is_object)
+            .collect()
+    }
+
+    /// The --json events named `event`.
+    pub fn events(&self, event: &str) -> Vec<Value> {
+        self.json()
+            .into_iter()
+            .filter(|v| v["event"] == event)
+            .collect()
+    }
+
+    pub fn text(&self) -> String {
+        self.output.join("\n")
+    }
+
+    pub fn position(&self, want: impl Fn(&str) -> bool) -> Option<usize> {
+        self.output.iter().position(|line| want(line))
+    }
+}
+
+/// Blobs are printed alone on their line and are long single words.
+pub fn is_blob(line: &str) -> bool {
+    let line = line.trim();
+    line.len() > 100 && !line.contains(char::is_whitespace) && !line.starts_with('{')
+}
+
+/// Start both binaries and exchange the offer and answer between them.
+pub fn connect(offer_args: &[&str], answer_args: &[&str]) -> (Peer, Peer) {
+    let mut offer = Peer::spawn(offer_bin(), offer_args);
+    let mut answer = Peer::spawn(answer_bin(), answer_args);
+    answer.send_line(&offer.blob());
+    offer.send_line(&answer.blob());
+    (offer, answer)
+}
+
+/// A scratch path unique to this test process.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("webrtc-latency-{}-{}", std::process::id(), name))
+}
+
+/// Write a --replay schedule of `count` minimal pings `spacing_ms` apart,
+/// for bounded runs far faster than the one ping a second of the default.
+pub fn fast_schedule(name: &str, count: u64, spacing_ms: u64) -> PathBuf {
+    let sends: Vec<Value> = (0..count)
+        .map(|i| serde_json::json!({ "at_us": i * spacing_ms * 1000, "size": 17 }))
+        .collect();
+    let path = temp_path(name);
+    std::fs::write(&path, serde_json::json!({ "sends": sends }).to_string()).unwrap();
+    path
+}
@@ -0,0 +1,86 @@
+//! An in-process answerer that takes whatever arrives and never echoes,
+//! standing in for a pure sink or for a peer whose return path is blocked.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc_latency::signal;
+use webrtc_latency::wire::{FormatDescriptor, Frame};
+
+pub struct Sink {
+    runtime: Runtime,
+    pc: Arc<RTCPeerConnection>,
+    received: Arc<AtomicUsize>,
+}
+
+impl Sink {
+    /// Answer `offer_blob` and return the answer blob to paste back. With
+    /// `hello`, announce our wire format on open as the real answer does.
+    pub fn answer(offer_blob: &str, hello: bool) -> (Sink, String) {
+        let runtime = Runtime::new().unwrap();
+        let received = Arc::new(AtomicUsize::new(0));
+        let (pc, blob) = runtime.block_on(Self::connect(offer_blob, hello, Arc::clone(&received)));
+        let sink = Sink {
+            runtime,
+            pc,
+            received,
+        };
+        (sink, blob)
+    }
+
+    async fn connect(
+        offer_blob: &str,
+        hello: bool,
+        received: Arc<AtomicUsize>,
+    ) -> (Arc<RTCPeerConnection>, String) {
+        let api = APIBuilder::new().build();
+        let pc = Arc::new(
+            api.new_peer_connection(RTCConfiguration::default())
+                .await
+                .unwrap(),
+        );
+        pc.on_data_channel(Box::new(move |dc: Arc<RTCDataChannel>| {
+            let received = Arc::clone(&received);
+            Box::pin(async move {
+                dc.on_message(Box::new(move |_: DataChannelMessage| {
+                    received.fetch_add(1, Ordering::SeqCst);
+                    Box::pin(async {})
+                }));
+                if hello {
+                    let dc2 = Arc::clone(&dc);
+                    dc.on_open(Box::new(move || {
+                        Box::pin(async move {
+                            let hello = Frame::Hello(FormatDescriptor::current()).encode();
+                            dc2.send(&hello).await.unwrap();
+                        })
+                    }));
+                }
+            })
+        }));
+        pc.set_remote_description(signal::decode_sdp(offer_blob).unwrap())
+            .await
+            .unwrap();
+        let answer = pc.create_answer(None).await.unwrap();
+        let mut gathered = pc.gathering_complete_promise().await;
+        pc.set_local_description(answer).await.unwrap();
+        let _ = gathered.recv().await;
+        let answer = pc.local_description().await.unwrap();
+        (pc, signal::encode_sdp(&answer).unwrap())
+    }
+
+    /// Messages received so far.
+    pub fn received(&self) -> usize {
+        self.received.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        let _ = self.runtime.block_on(self.pc.close());
+    }
+}
@@ -0,0 +1,511 @@
+//! End-to-end runs of the two binaries over loopback.
+
+mod common;
+
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use common::sink::Sink;
+use common::{connect, fast_schedule, Peer};
+use webrtc_latency::loss::SeededDrop;
+use webrtc_latency::signal;
+
+/// Long enough for a connection, a short bounded run and its drain.
+const RUN: Duration = Duration::from_secs(30);
+
+#[test]
+fn every_ping_is_echoed() {
+    let schedule = fast_schedule("echo.json", 10, 20);
+    let replay = schedule.to_str().unwrap();
+    let (offer, answer) = connect(&["--replay", replay, "--json"], &[]);
+    let offer = offer.wait(RUN);
+    answer.stop();
+    std::fs::remove_file(&schedule).unwrap();
+    assert!(offer.status.success(), "{}", offer.text());
+
+    let summary = &offer.events("summary")[0]["summary"];
+    assert_eq!(summary["sent"], 10, "{}", summary);
+    assert_eq!(summary["received"], 10, "{}", summary);
+    assert_eq!(summary["lost"], 0, "{}", summary);
+}
+
+#[test]
+fn offer_loses_exactly_the_seeded_drops() {
+    let schedule = fast_schedule("recv-drop.json", 40, 20);
+    let replay = schedule.to_str().unwrap();
+    let (offer, answer) = connect(
+        &["--replay", replay, "--log-gaps", "--json"],
+        &["--recv-drop", "0.2", "--seed", "7", "--json"],
+    );
+    let offer = offer.wait(RUN);
+    let answer = answer.stop();
+    std::fs::remove_file(&schedule).unwrap();
+    assert!(offer.status.success(), "{}", offer.text());
+
+    let dropped: BTreeSet<u64> = answer
+        .events("recv_drop")
+        .iter()
+        .map(|e| e["seq"].as_u64().unwrap())
+        .collect();
+    let expected: BTreeSet<u64> = (0..40)
+        .filter(|&s| SeededDrop::new(0.2, 7).drops(s))
+        .collect();
+    assert_eq!(dropped, expected);
+    // Seed 7 drops the last ping, which only the end of the run writes off
+    assert!(dropped.contains(&39));
+
+    let lost: BTreeSet<u64> = offer
+        .events("gap")
+        .iter()
+        .flat_map(|e| e["gap"]["first"].as_u64().unwrap()..=e["gap"]["last"].as_u64().unwrap())
+        .collect();
+    assert_eq!(lost, expected);
+    let summary = &offer.events("summary")[0]["summary"];
+    assert_eq!(summary["lost"], expected.len() as u64);
+    assert_eq!(summary["in_flight"], 0);
+}
+
+#[test]
+fn protocol_is_seen_on_both_ends() {
+    let (offer, answer) = connect(
+        &["--protocol", "myproto", "--once"],
+        &["--protocol", "myproto"],
+    );
+    let offer = offer.wait(RUN);
+    let answer = answer.stop();
+    assert!(offer.status.success(), "{}", offer.text());
+    assert!(
+        offer
+            .position(|l| l == r#"DataChannel open (protocol: "myproto")"#)
+            .is_some(),
+        "{}",
+        offer.text()
+    );
+    let received = r#"DataChannel received: latency (protocol: "myproto")"#;
+    assert!(
+        answer.position(|l| l == received).is_some(),
+        "{}",
+        answer.text()
+    );
+    assert!(answer
+        .position(|l| l.starts_with("unexpected protocol"))
+        .is_none());
+}
+
+#[test]
+fn protocol_mismatch_closes_the_channel() {
+    let (offer, answer) = connect(
+        &["--protocol", "other", "--once", "--error-json"],
+        &["--protocol", "myproto", "--reject-protocol-mismatch"],
+    );
+    let offer = offer.wait(RUN);
+    let answer = answer.stop();
+    let refused = r#"unexpected protocol "other" on latency, expected "myproto""#;
+    assert!(
+        answer.position(|l| l == refused).is_some(),
+        "{}",
+        answer.text()
+    );
+    // The closed channel never echoes, so the single ping goes unanswered
+    assert_eq!(offer.status.code(), Some(1), "{}", offer.text());
+    let error = offer.json().into_iter().find(|v| v.get("kind").is_some());
+    assert_eq!(error.unwrap()["kind"], "no_connectivity");
+}
+
+#[test]
+fn selected_pair_ports_stay_in_the_configured_ranges() {
+    let offer_range = 41000..=41099;
+    let answer_range = 42000..=42099;
+    let (offer, answer) = connect(
+        &[
+            "--port-min",
+            "41000",
+            "--port-max",
+            "41099",
+            "--once",
+            "--json",
+        ],
+        &["--port-min", "42000", "--port-max", "42099", "--json"],
+    );
+    let offer = offer.wait(RUN);
+    let answer = answer.stop();
+    assert!(offer.status.success(), "{}", offer.text());
+    for (peer, local, remote) in [
+        (&offer, &offer_range, &answer_range),
+        (&answer, &answer_range, &offer_range),
+    ] {
+        let events = peer.events("selected_pair");
+        let pair = &events.first().unwrap_or_else(|| panic!("{}", peer.text()))["pair"];
+        let port = |end: &str| pair[end]["port"].as_u64().unwrap() as u16;
+        assert!(local.contains(&port("local")), "{}", pair);
+        assert!(remote.contains(&port("remote")), "{}", pair);
+        assert_eq!(pair["local"]["candidate_type"], "host");
+        assert_eq!(
+            pair["local"]["protocol"].as_str().unwrap().get(..3),
+            Some("udp")
+        );
+    }
+}
+
+#[test]
+fn summary_comes_before_the_close_logs() {
+    let log = [("RUST_LOG", "info")];
+    let mut offer = Peer::spawn_with_env(common::offer_bin(), &["--once"], &log);
+    let mut answer = Peer::spawn(common::answer_bin(), &[]);
+    answer.send_line(&offer.blob());
+    offer.send_line(&answer.blob());
+    let offer = offer.wait(RUN);
+    answer.stop();
+    assert!(offer.status.success(), "{}", offer.text());
+
+    let summary = offer
+        .position(|l| l == "=== Summary ===")
+        .unwrap_or_else(|| panic!("{}", offer.text()));
+    let closed = offer
+        .position(|l| l.contains("peer connection state changed: closed"))
+        .unwrap_or_else(|| panic!("{}", offer.text()));
+    assert!(summary < closed, "{}", offer.text());
+    // Nothing from the teardown lands inside the summary either
+    let is_log = |l: &str| l.starts_with("[20");
+    assert!(
+        !offer.output[summary..summary + 3].iter().any(|l| is_log(l)),
+        "{}",
+        offer.text()
+    );
+    let teardown = offer.position(|l| is_log(l) && l.to_lowercase().contains("close"));
+    assert!(teardown > Some(summary), "{}", offer.text());
+}
+
+#[test]
+fn listener_answers_offers_in_sequence() {
+    let mut answer = Peer::spawn(common::answer_bin(), &["--listen"]);
+    for id in 1..=2 {
+        let mut offer = Peer::spawn(common::offer_bin(), &["--once"]);
+        answer.send_line(&offer.blob());
+        offer.send_line(&answer.blob());
+        let offer = offer.wait(RUN);
+        assert!(
+            offer.status.success(),
+            "connection {}: {}",
+            id,
+            offer.text()
+        );
+        // One slot, so the next offer is only read once this one is gone
+        let closed = format!("Connection {} closed", id);
+        answer.expect(|l| l == closed, RUN);
+    }
+    let answer = answer.stop();
+    assert_eq!(
+        answer.position(|l| l.contains("failed")),
+        None,
+        "{}",
+        answer.text()
+    );
+}
+
+#[test]
+fn blocked_return_path_fails_fast() {
+    let mut offer = Peer::spawn(
+        common::offer_bin(),
+        &["--confirm-connectivity", "--once", "--error-json"],
+    );
+    let (sink, blob) = Sink::answer(&offer.blob(), true);
+    offer.send_line(&blob);
+    offer.expect(|l| l.starts_with("DataChannel open"), RUN);
+    let opened = Instant::now();
+    let offer = offer.wait(RUN);
+    // The probe is retried for 3 s; the ping itself is never attempted
+    assert!(
+        opened.elapsed() < Duration::from_secs(6),
+        "{:?}",
+        opened.elapsed()
+    );
+    assert_eq!(offer.status.code(), Some(1), "{}", offer.text());
+    let error = offer.json().into_iter().find(|v| v.get("kind").is_some());
+    let error = error.unwrap_or_else(|| panic!("{}", offer.text()));
+    assert_eq!(error["kind"], "no_connectivity");
+    assert!(
+        error["message"]
+            .as_str()
+            .unwrap()
+            .contains("connectivity probe"),
+        "{}",
+        error
+    );
+    assert!(offer.position(|l| l.starts_with("seq=0")).is_none());
+    // Hello, then the 500 ms probe retries
+    assert!(sink.received() >= 4, "{}", sink.received());
+}
+
+#[test]
+fn connectivity_only_succeeds_against_a_silent_sink() {
+    let mut offer = Peer::spawn(common::offer_bin(), &["--once", "--connectivity-only"]);
+    // No Hello, no echoes: the offer must not wait for either
+    let (sink, blob) = Sink::answer(&offer.blob(), false);
+    offer.send_line(&blob);
+    let offer = offer.wait(RUN);
+    assert!(offer.status.success(), "{}", offer.text());
+    assert!(offer
+        .position(|l| l == "Connectivity confirmed: channel open and a message sent")
+        .is_some());
+    assert!(offer.position(|l| l.starts_with("seq=0")).is_none());
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while sink.received() == 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(sink.received(), 1);
+}
+
+#[test]
+fn both_channels_are_measured_side_by_side() {
+    let (offer, answer) = connect(
+        &["--compare-reliability", "--count", "12", "--json"],
+        &["--recv-drop", "0.2", "--seed", "7"],
+    );
+    let offer = offer.wait(RUN);
+    answer.stop();
+    assert!(offer.status.success(), "{}", offer.text());
+
+    let events = offer.events("comparison");
+    let comparison = events.first().unwrap_or_else(|| panic!("{}", offer.text()));
+    let (reliable, unreliable) = (&comparison["reliable"], &comparison["unreliable"]);
+    // The same pings on both, so the seeded drops hit both alike
+    let dropped = (0..12)
+        .filter(|&s| SeededDrop::new(0.2, 7).drops(s))
+        .count();
+    assert!(dropped > 0);
+    for report in [reliable, unreliable] {
+        assert_eq!(report["sent"], 12, "{}", comparison);
+        assert_eq!(report["lost"], dropped as u64, "{}", comparison);
+        assert_eq!(report["received"], 12 - dropped as u64, "{}", comparison);
+        assert!(report["mean_ms"].as_f64().unwrap() > 0.0, "{}", comparison);
+    }
+    // Each channel's RTTs are its own
+    assert_ne!(reliable["mean_ms"], unreliable["mean_ms"], "{}", comparison);
+    // The main summary is the reliable channel's
+    let summary = &offer.events("summary")[0]["summary"];
+    assert_eq!(summary["mean_ms"], reliable["mean_ms"]);
+}
+
+#[test]
+fn max_bytes_stops_before_the_cap() {
+    // 200 bytes a ping there and back, so the cap leaves room for four
+    let (offer, answer) = connect(
+        &["--max-bytes", "0.9k", "--payload-size", "100", "--json"],
+        &[],
+    );
+    let offer = offer.wait(RUN);
+    answer.stop();
+    assert!(offer.status.success(), "{}", offer.text());
+    assert!(
+        offer
+            .position(|l| l == "Reached --max-bytes: 4 pings, 800 bytes sent and echoed")
+            .is_some(),
+        "{}",
+        offer.text()
+    );
+    let summary = &offer.events("summary")[0]["summary"];
+    assert_eq!(summary["sent"], 4, "{}", summary);
+    assert_eq!(summary["received"], 4, "{}", summary);
+}
+
+#[test]
+fn banner_describes_the_session_once() {
+    let (offer, answer) = connect(
+        &["--banner", "--json", "--count", "2"],
+        &["--banner", "--json"],
+    );
+    let offer = offer.wait(RUN);
+    let answer = answer.stop();
+    assert!(offer.status.success(), "{}", offer.text());
+
+    let banner = |peer: &common::Finished| {
+        let banners = peer.events("banner");
+        assert_eq!(banners.len(), 1, "{}", peer.text());
+        banners[0]["banner"].clone()
+    };
+    let (ours, theirs) = (banner(&offer), banner(&answer));
+    assert_eq!(ours["role"], "offer");
+    assert_eq!(theirs["role"], "answer");
+    for (banner, milestones) in [
+        (
+            &ours,
+            ["offer ready", "answer applied", "connected", "channel open"],
+        ),
+        (
+            &theirs,
+            ["offer applied", "answer ready", "connected", "channel open"],
+        ),
+    ] {
+        for end in ["local", "remote"] {
+            let endpoint = &banner["pair"][end];
+            assert!(!endpoint["ip"].as_str().unwrap().is_empty(), "{}", banner);
+            assert!(endpoint["port"].as_u64().unwrap() > 0, "{}", banner);
+            assert_eq!(endpoint["candidate_type"], "host", "{}", banner);
+        }
+        assert_eq!(banner["channel"]["label"], "latency", "{}", banner);
+        assert_eq!(banner["channel"]["ordered"], true, "{}", banner);
+        let events: Vec<_> = banner["timings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(events, milestones, "{}", banner);
+    }
+    // Each side shows both fingerprints, and they agree
+    let fingerprint = |b: &serde_json::Value, which: &str| {
+        let f = b[which].as_str().unwrap().to_string();
+        assert!(f.starts_with("sha-256 "), "{}", b);
+        f
+    };
+    assert_eq!(
+        fingerprint(&ours, "local_fingerprint"),
+        fingerprint(&theirs, "remote_fingerprint")
+    );
+    assert_eq!(
+        fingerprint(&ours, "remote_fingerprint"),
+        fingerprint(&theirs, "local_fingerprint")
+    );
+    // Both ends of the selected pair, seen from either side
+    assert_eq!(ours["pair"]["local"], theirs["pair"]["remote"]);
+    assert_eq!(ours["pair"]["remote"], theirs["pair"]["local"]);
+}
+
+#[test]
+fn quiet_overrides_the_banner() {
+    let (offer, answer) = connect(
+        &["--banner", "--quiet", "--json", "--count", "2"],
+        &["--banner", "--quiet", "--json"],
+    );
+    let offer = offer.wait(RUN);
+    let answer = answer.stop();
+    assert!(offer.status.success(), "{}", offer.text());
+    for peer in [&offer, &answer] {
+        assert!(peer.events("banner").is_empty(), "{}", peer.text());
+        assert!(peer.position(|l| l.starts_with("role:")).is_none());
+    }
+}
+
+#[test]
+fn trickle_only_peers_connect_on_pasted_candidates() {
+    let mut offer = Peer::spawn(
+        common::offer_bin(),
+        &["--no-trickle-in-sdp", "--count", "3", "--json"],
+    );
+    let mut answer = Peer::spawn(common::answer_bin(), &["--no-trickle-in-sdp"]);
+    let no_candidates = |blob: &str| {
+        let sdp = signal::decode_sdp(blob).unwrap().sdp;
+        assert!(!sdp.contains("a=candidate"), "{}", sdp);
+    };
+    let blob = offer.blob();
+    no_candidates(&blob);
+    answer.send_line(&blob);
+    let blob = answer.blob();
+    no_candidates(&blob);
+    offer.send_line(&blob);
+
+    // Paste each side's candidates into the other as they are gathered
+    let deadline = Instant::now() + RUN;
+    let (mut to_answer, mut to_offer) = (0, 0);
+    while Instant::now() < deadline
+        && !offer
+            .output()
+            .iter()
+            .any(|l| l.starts_with("DataChannel open"))
+    {
+        let lines = offer.candidates();
+        for line in &lines[to_answer..] {
+            answer.send_line(line);
+        }
+        to_answer = lines.len();
+        let lines = answer.candidates();
+        for line in &lines[to_offer..] {
+            offer.send_line(line);
+        }
+        to_offer = lines.len();
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert!(to_answer > 0 && to_offer > 0);
+
+    let offer = offer.wait(RUN);
+    answer.stop();
+    assert!(offer.status.success(), "{}", offer.text());
+    assert!(offer
+        .position(|l| l.starts_with("DataChannel open"))
+        .is_some());
+    let summary = &offer.events("summary")[0]["summary"];
+    assert!(summary["received"].as_u64().unwrap() >= 1, "{}", summary);
+}
+
+/// The candidate lines of an SDP, as (foundation, is IPv6) with the line.
+fn candidate_lines(sdp: &str) -> Vec<(String, bool, String)> {
+    sdp.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.strip_prefix("a=candidate:")?.split(' ').collect();
+            Some((
+                fields[0].to_string(),
+                fields[4].contains(':'),
+                line.to_string(),
+            ))
+        })
+        .collect()
+}
+
+#[test]
+fn listener_refuses_a_foreign_ip_version_and_keeps_serving() {
+    let first = Peer::spawn(common::offer_bin(), &[]);
+    let mut offer = signal::decode_sdp(&first.blob()).unwrap();
+    let candidates = candidate_lines(&offer.sdp);
+    if !candidates.iter().any(|(_, v6, _)| *v6) || candidates.iter().all(|(_, v6, _)| *v6) {
+        eprintln!("skipped: this host has no IPv4 and IPv6 host candidates to split");
+        return;
+    }
+    // Foundations hash the address, so the listener's candidates on this
+    // host share them: drop its IPv4 ones, and the offer's IPv6 ones
+    let rules: Vec<_> = candidates
+        .iter()
+        .filter(|(_, v6, _)| !v6)
+        .map(|(f, _, _)| serde_json::json!({ "op": "remove", "prefix": format!("a=candidate:{} ", f) }))
+        .collect();
+    let munge = common::temp_path("v6-only.json");
+    std::fs::write(&munge, serde_json::Value::from(rules).to_string()).unwrap();
+    for (_, v6, line) in &candidates {
+        if *v6 {
+            offer.sdp = offer.sdp.replace(&format!("{}\r\n", line), "");
+        }
+    }
+
+    let mut listener = Peer::spawn(
+        common::answer_bin(),
+        &["--listen", "--sdp-munge", munge.to_str().unwrap()],
+    );
+    listener.send_line(&signal::encode_sdp(&offer).unwrap());
+    let refused = listener.expect(|l| l.starts_with("connection 1 failed"), RUN);
+    assert!(
+        refused.contains("no common IP version between peers (local: v6, remote: v4)"),
+        "{}",
+        refused
+    );
+    first.stop();
+
+    // The next offer shares IPv6 with the listener and connects
+    let mut second = Peer::spawn(common::offer_bin(), &["--count", "2"]);
+    listener.expect(|l| l.contains("Paste OFFER from the next peer"), RUN);
+    listener.send_line(&second.blob());
+    second.send_line(&listener.blob());
+    let second = second.wait(RUN);
+    let listener = listener.stop();
+    std::fs::remove_file(&munge).unwrap();
+    assert!(second.status.success(), "{}", second.text());
+    assert!(listener
+        .position(|l| l == "Connection 2 answered")
+        .is_some());
+    assert!(
+        second
+            .position(|l| l.starts_with("Selected pair: udp6"))
+            .is_some(),
+        "{}",
+        second.text()
+    );
+}